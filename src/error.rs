@@ -0,0 +1,148 @@
+use std::{fmt, io};
+
+/// Everything that can stop rustcping before or while it starts probing.
+#[derive(Debug)]
+pub enum Error {
+    /// The command line could not be parsed or one of its values is invalid.
+    Args(clap::Error),
+    /// The name resolution of the target failed.
+    Resolve { host: String, source: io::Error },
+    /// The name resolution of the target succeeded but returned no address.
+    NoAddress { host: String },
+    /// The Ctrl-C handler could not be installed.
+    Signal(ctrlc::Error),
+    /// The probes cannot be sent from the requested source address or interface.
+    Bind(io::Error),
+    /// The traceroute could not be run, usually for lack of CAP_NET_RAW.
+    Traceroute(io::Error),
+    /// The TLS settings of the probes cannot be built.
+    Tls(String),
+}
+
+impl Error {
+    /// The exit code of the process when it terminates because of this error.
+    /// Argument errors keep the exit codes chosen by clap.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Args(e) => e.exit_code(),
+            Error::Resolve { .. } => 3,
+            Error::NoAddress { .. } => 4,
+            Error::Signal(_) => 5,
+            Error::Bind(_) => 6,
+            Error::Traceroute(_) => 7,
+            Error::Tls(_) => 8,
+        }
+    }
+
+    /// Prints the error in the format the user expects for its kind.
+    pub fn report(&self) {
+        match self {
+            Error::Args(e) => _ = e.print(),
+            _ => eprintln!("rustcping: {self}"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Args(e) => write!(f, "{e}"),
+            Error::Resolve { host, source } => {
+                write!(f, "cannot resolve host '{host}': {source}")
+            }
+            Error::NoAddress { host } => write!(f, "host '{host}' has no address"),
+            Error::Signal(e) => write!(f, "cannot set the Ctrl-C handler: {e}"),
+            Error::Bind(e) => write!(f, "cannot send probes from the given source: {e}"),
+            Error::Traceroute(e) => write!(f, "cannot trace the route: {e}"),
+            Error::Tls(reason) => write!(f, "cannot set up TLS: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Args(e) => Some(e),
+            Error::Resolve { source, .. } => Some(source),
+            Error::NoAddress { .. } => None,
+            Error::Signal(e) => Some(e),
+            Error::Bind(e) => Some(e),
+            Error::Traceroute(e) => Some(e),
+            Error::Tls(_) => None,
+        }
+    }
+}
+
+impl From<clap::Error> for Error {
+    fn from(e: clap::Error) -> Self {
+        Error::Args(e)
+    }
+}
+
+impl From<ctrlc::Error> for Error {
+    fn from(e: ctrlc::Error) -> Self {
+        Error::Signal(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{error::Error, user_input::parse};
+
+    fn args_error() -> Error {
+        parse(["EXEC_NAME", "1.2.3.4", "--timeout", "-2.3"])
+            .unwrap_err()
+            .into()
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let codes = [
+            args_error().exit_code(),
+            Error::Resolve {
+                host: "example.invalid".to_owned(),
+                source: io::Error::other("error"),
+            }
+            .exit_code(),
+            Error::NoAddress {
+                host: "example.invalid".to_owned(),
+            }
+            .exit_code(),
+            Error::Signal(ctrlc::Error::MultipleHandlers).exit_code(),
+            Error::Bind(io::Error::other("error")).exit_code(),
+            Error::Traceroute(io::Error::other("error")).exit_code(),
+            Error::Tls("error".to_owned()).exit_code(),
+        ];
+        for (i, code) in codes.iter().enumerate() {
+            assert_ne!(*code, 0);
+            assert!(!codes[i + 1..].contains(code));
+        }
+    }
+
+    #[test]
+    fn test_args_exit_code_is_usage() {
+        assert_eq!(args_error().exit_code(), 2)
+    }
+
+    #[test]
+    fn test_resolve_message() {
+        let e = Error::Resolve {
+            host: "example.invalid".to_owned(),
+            source: io::Error::other("Name or service not known"),
+        };
+        assert_eq!(
+            e.to_string(),
+            "cannot resolve host 'example.invalid': Name or service not known"
+        )
+    }
+
+    #[test]
+    fn test_no_address_message() {
+        let e = Error::NoAddress {
+            host: "example.invalid".to_owned(),
+        };
+        assert_eq!(e.to_string(), "host 'example.invalid' has no address")
+    }
+}
@@ -1,62 +1,363 @@
 use std::{
     env,
-    error::Error,
-    net::{SocketAddr, ToSocketAddrs},
-    sync::mpsc::channel,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    process,
+    sync::mpsc::{channel, Receiver},
     thread,
 };
 
+mod error;
 pub mod pinger;
 pub mod printer;
+mod target;
 mod tcping;
+mod traceroute;
 mod tracker;
 mod user_input;
 
-use pinger::{PingTimeout, PingWithoutTimeout, Pinger};
-use printer::print_probe;
-use tcping::tcping;
-use tracker::Info;
-use user_input::parse;
+use error::Error;
+use pinger::{
+    Bind, Dns, Ftp, Grpc, Handshake, Mqtt, Mysql, PingProtocol, PingSyn, PingTimeout, PingUdp,
+    PingUnix, PingWithoutTimeout, Pinger, Postgres, Redis, ResponseCheck, Session, Smtp,
+    SourcePorts, Ssh, Tls, Tunnel, Websocket,
+};
+use printer::{print_header, print_hop, print_probe, print_traceroute_header};
+use target::Protocol;
+use tcping::{tcping, SystemClock};
+use traceroute::{traceroute, Traceroute};
+use tracker::{Info, Probe};
+use user_input::{parse, UserInput};
 
 use crate::printer::print_final_stats;
 
-fn get_socket(url: &String, port: u16) -> Result<SocketAddr, std::io::Error> {
-    format!("{url}:{port}")
+/// The most bytes read from the answer to the data sent with `--send`.
+const RESPONSE_MAX_LEN: usize = 4096;
+
+/// Resolves the target, picking an address of the same family as the source
+/// address when one is given.
+fn get_socket(url: &str, port: u16, source: Option<IpAddr>) -> Result<SocketAddr, Error> {
+    (url, port)
         .to_socket_addrs()
-        .map(|v| v.as_ref()[0])
+        .map_err(|source| Error::Resolve {
+            host: url.to_owned(),
+            source,
+        })?
+        .find(|socket| source.is_none_or(|source| source.is_ipv4() == socket.is_ipv4()))
+        .ok_or_else(|| Error::NoAddress {
+            host: url.to_owned(),
+        })
+}
+
+fn new_pinger(
+    user_input: &UserInput,
+    socket: SocketAddr,
+    bind: &Bind,
+    session: &Session,
+) -> Result<Box<dyn Pinger>, Error> {
+    let conn_timeout = user_input.timeout.and_then(|t| t.to_std().ok());
+    if user_input.udp {
+        let pinger = PingUdp::new(
+            socket,
+            bind,
+            user_input.send.clone(),
+            conn_timeout,
+            user_input.expect.clone(),
+        )
+        .map_err(Error::Bind)?;
+        return Ok(Box::new(pinger));
+    }
+    if user_input.syn {
+        let timeout = conn_timeout
+            .or(user_input.interval_between_probes.to_std().ok())
+            .unwrap_or_default();
+        let pinger = PingSyn::new(socket, timeout, bind.clone()).map_err(Error::Bind)?;
+        return Ok(Box::new(pinger));
+    }
+    let timeout = conn_timeout.unwrap_or(std::time::Duration::from_secs(1));
+    let handshake: Box<dyn Handshake> = match user_input.protocol {
+        Protocol::Tcp => {
+            return Ok(match conn_timeout {
+                Some(conn_timeout) => Box::new(PingTimeout {
+                    socket,
+                    conn_timeout,
+                    bind: bind.clone(),
+                    session: session.clone(),
+                }),
+                None => Box::new(PingWithoutTimeout {
+                    socket,
+                    bind: bind.clone(),
+                    session: session.clone(),
+                }),
+            })
+        }
+        Protocol::Redis => Box::new(Redis { timeout }),
+        Protocol::Postgres => Box::new(Postgres { timeout }),
+        Protocol::Mysql => Box::new(Mysql { timeout }),
+        Protocol::Dns => Box::new(Dns {
+            timeout,
+            name: user_input.dns_name.clone(),
+            record_type: user_input.dns_type,
+        }),
+        Protocol::Smtp => Box::new(Smtp {
+            timeout,
+            ehlo: user_input.ehlo.clone(),
+        }),
+        Protocol::Ssh => Box::new(Ssh { timeout }),
+        Protocol::Ftp => Box::new(Ftp { timeout }),
+        Protocol::Websocket => Box::new(Websocket {
+            timeout,
+            host: host_header(
+                &user_input.url,
+                session
+                    .tunnel
+                    .as_ref()
+                    .map_or(socket.port(), |tunnel| tunnel.port),
+            ),
+            path: user_input.ws_path.clone(),
+            headers: user_input.ws_headers.clone(),
+            ping: user_input.ws_ping,
+        }),
+        Protocol::Mqtt => Box::new(Mqtt {
+            timeout,
+            version: user_input.mqtt_version,
+            // brokers drop the session of a client id connecting again, so
+            // two instances should not share one
+            client_id: user_input
+                .mqtt_client_id
+                .clone()
+                .unwrap_or_else(|| format!("rustcping-{}", process::id())),
+            username: user_input.mqtt_username.clone(),
+            password: user_input.mqtt_password.clone(),
+        }),
+        Protocol::Grpc => Box::new(Grpc {
+            timeout,
+            authority: host_header(
+                &user_input.url,
+                session
+                    .tunnel
+                    .as_ref()
+                    .map_or(socket.port(), |tunnel| tunnel.port),
+            ),
+            service: user_input.grpc_service.clone(),
+            tls: match user_input.tls {
+                true => Some(Tls::new(&user_input.url, user_input.tls_verify).map_err(Error::Tls)?),
+                false => None,
+            },
+        }),
+    };
+    Ok(Box::new(PingProtocol {
+        socket,
+        conn_timeout,
+        bind: bind.clone(),
+        session: session.clone(),
+        handshake,
+    }))
+}
+
+/// The value of the Host header for `host` on `port`.
+fn host_header(host: &str, port: u16) -> String {
+    let host = match host.contains(':') {
+        true => format!("[{host}]"),
+        false => host.to_owned(),
+    };
+    match port {
+        80 => host,
+        port => format!("{host}:{port}"),
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let user_input = parse(env::args());
-    let socket = get_socket(&user_input.url, user_input.port)?;
+fn run() -> Result<(), Error> {
+    let mut user_input = parse(env::args())?;
+    let closer_rx = closer()?;
+    if user_input.unix {
+        let pinger = PingUnix {
+            path: user_input.url.clone().into(),
+            timeout: user_input.timeout.and_then(|t| t.to_std().ok()),
+        };
+        let target = format!("unix:{}", user_input.url);
+        // the socket has no port, its probes are tracked under 0
+        watch(user_input, target, &[(0, &pinger)], Vec::new(), closer_rx);
+        return Ok(());
+    }
+    // through a proxy, the proxy resolves the target and all the probes
+    // connect to it
+    let socket = match &user_input.proxy {
+        Some(proxy) => get_socket(&proxy.host, proxy.port, user_input.source)?,
+        None => get_socket(&user_input.url, user_input.ports[0], user_input.source)?,
+    };
     let conn_timeout = user_input.timeout;
-    let (probe_sx, probe_rx) = channel();
-    let (ctrlc_sx, ctrlc_rx) = channel();
-    let mut info = Info::new(user_input.clone(), socket.ip());
-    let tracker_handle = thread::spawn(move || {
-        while let Ok(probe) = probe_rx.recv() {
-            info.track(&probe);
-            print_probe(&info, &probe);
+    let bind = Bind {
+        source: user_input.source,
+        interface: user_input.interface.clone(),
+        source_ports: user_input.source_ports.clone().map(SourcePorts::new),
+        ttl: user_input.ttl,
+        options: user_input.socket_options.clone(),
+    };
+    let socket_options = bind.check(&socket).map_err(Error::Bind)?;
+    if user_input.syn {
+        // checked once, and not for every port
+        if let Err(e) = PingSyn::new(socket, std::time::Duration::ZERO, bind.clone()) {
+            eprintln!("rustcping: cannot send SYN probes ({e}), falling back to full connections");
+            user_input.syn = false;
         }
-        print_final_stats(&info)
-    });
-    let tcping_th = std::thread::current();
+    }
+    let read_timeout = conn_timeout
+        .and_then(|t| t.to_std().ok())
+        .unwrap_or(std::time::Duration::from_secs(1));
+    let session = Session {
+        tunnel: None,
+        proxy_header: user_input.proxy_protocol.clone(),
+        banner: user_input.read_banner.map(|max_len| ResponseCheck {
+            max_len,
+            timeout: read_timeout,
+            expect: user_input.expect_banner.clone(),
+        }),
+        request: user_input.send.clone(),
+        response: user_input.send.as_ref().map(|_| ResponseCheck {
+            max_len: RESPONSE_MAX_LEN,
+            timeout: read_timeout,
+            expect: user_input.expect.clone(),
+        }),
+        hold: user_input.hold.and_then(|t| t.to_std().ok()),
+        close: user_input.close,
+        close_timeout: read_timeout,
+        tcp_info: user_input.tcp_info,
+    };
+    if let Some(max_hops) = user_input.traceroute {
+        let timeout = conn_timeout
+            .and_then(|t| t.to_std().ok())
+            .unwrap_or(std::time::Duration::from_secs(1));
+        let tracer = Traceroute::new(socket, timeout, bind).map_err(Error::Traceroute)?;
+        print_traceroute_header(&user_input, socket, max_hops);
+        return traceroute(&tracer, max_hops, &closer_rx, |ttl, hop| {
+            print_hop(&user_input, ttl, hop)
+        })
+        .map_err(Error::Traceroute);
+    }
+    let pingers: Vec<(u16, Box<dyn Pinger>)> = user_input
+        .ports
+        .iter()
+        .map(|port| match &user_input.proxy {
+            Some(proxy) => {
+                let session = Session {
+                    tunnel: Some(Tunnel {
+                        kind: proxy.kind,
+                        host: user_input.url.clone(),
+                        port: *port,
+                        timeout: read_timeout,
+                    }),
+                    ..session.clone()
+                };
+                Ok((*port, new_pinger(&user_input, socket, &bind, &session)?))
+            }
+            None => {
+                let socket = SocketAddr::new(socket.ip(), *port);
+                Ok((*port, new_pinger(&user_input, socket, &bind, &session)?))
+            }
+        })
+        .collect::<Result<_, Error>>()?;
+    let pingers: Vec<(u16, &dyn Pinger)> = pingers
+        .iter()
+        .map(|(port, pinger)| (*port, pinger.as_ref()))
+        .collect();
+    // through a proxy, the address of the target is not known, and the one
+    // connected to is the proxy's
+    let target = match &user_input.proxy {
+        Some(_) => user_input.url.clone(),
+        None => format!("{} ({})", user_input.url, socket.ip()),
+    };
+    watch(user_input, target, &pingers, socket_options, closer_rx);
+    Ok(())
+}
+
+/// Returns the receiver signaled on Ctrl-C, which also unparks the current
+/// thread so that it does not wait for the next probe.
+fn closer() -> Result<Receiver<()>, Error> {
+    let (ctrlc_sx, ctrlc_rx) = channel();
+    let tcping_th = thread::current();
     ctrlc::set_handler(move || {
         _ = ctrlc_sx.send(());
         tcping_th.unpark();
-    })
-    .expect("Error setting Ctrl-C handler");
+    })?;
+    Ok(ctrlc_rx)
+}
 
-    let pinger: Box<dyn Pinger> = if let Some(t) = conn_timeout {
-        Box::new(PingTimeout {
-            socket,
-            conn_timeout: t.to_std().unwrap(),
+/// Probes with the pingers until the count is reached or `closer_rx` is
+/// signaled, printing each probe, shown as going to `target`, and then the
+/// statistics, which list the `socket_options` in effect.
+fn watch(
+    user_input: UserInput,
+    target: String,
+    pingers: &[(u16, &dyn Pinger)],
+    socket_options: Vec<String>,
+    closer_rx: Receiver<()>,
+) {
+    let (probe_sx, probe_rx) = channel::<Probe>();
+    let mut infos: Vec<Info> = pingers
+        .iter()
+        .map(|(port, _)| Info {
+            socket_options: socket_options.clone(),
+            ..Info::new(user_input.clone(), target.clone(), *port)
         })
-    } else {
-        Box::new(PingWithoutTimeout { socket })
-    };
-    tcping(probe_sx, ctrlc_rx, pinger.as_ref(), user_input);
+        .collect();
+    print_header(&user_input, &target);
+    let tracker_handle = thread::spawn(move || {
+        while let Ok(probe) = probe_rx.recv() {
+            if let Some(info) = infos.iter_mut().find(|info| info.port == probe.port) {
+                info.track(&probe);
+                print_probe(info, &probe);
+            }
+        }
+        print_final_stats(&infos)
+    });
+    tcping(probe_sx, closer_rx, pingers, user_input, &SystemClock);
     _ = tracker_handle.join();
     // the threads close in this order: ctrlc => tcping => tracker => main
-    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        e.report();
+        process::exit(e.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Error, get_socket, host_header};
+
+    #[test]
+    fn test_get_socket_ip_addr() {
+        let socket = get_socket("127.0.0.1", 80, None).unwrap();
+        assert_eq!(socket, "127.0.0.1:80".parse().unwrap())
+    }
+
+    #[test]
+    fn test_get_socket_ipv6_addr() {
+        let socket = get_socket("::1", 22, None).unwrap();
+        assert_eq!(socket, "[::1]:22".parse().unwrap())
+    }
+
+    #[test]
+    fn test_get_socket_source_family() {
+        match get_socket("::1", 22, Some("127.0.0.1".parse().unwrap())) {
+            Err(Error::NoAddress { host }) => assert_eq!(host, "::1"),
+            other => panic!("expected no address, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_host_header() {
+        assert_eq!(host_header("gateway", 80), "gateway");
+        assert_eq!(host_header("gateway", 8080), "gateway:8080");
+        assert_eq!(host_header("::1", 8080), "[::1]:8080");
+    }
+
+    #[test]
+    fn test_get_socket_unresolvable() {
+        match get_socket("rustcping.invalid", 80, None) {
+            Err(Error::Resolve { host, .. }) => assert_eq!(host, "rustcping.invalid"),
+            other => panic!("expected a resolution error, got {other:?}"),
+        }
+    }
 }
@@ -1,26 +1,1494 @@
-use std::net::{SocketAddr, TcpStream};
+use std::{
+    cell::Cell,
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream},
+    ops::RangeInclusive,
+    os::fd::{AsRawFd, BorrowedFd},
+    time::{Duration, Instant},
+};
+
+use regex::bytes::Regex;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+mod dns;
+mod ftp;
+mod grpc;
+mod hpack;
+mod mqtt;
+mod mysql;
+mod postgres;
+mod proxy;
+mod proxy_protocol;
+mod redis;
+mod reply;
+mod smtp;
+mod ssh;
+mod syn;
+mod tcp_info;
+mod udp;
+mod unix;
+mod websocket;
+
+pub use dns::{encode_name, parse_record_type, Dns};
+pub use ftp::Ftp;
+pub use grpc::{Grpc, Tls};
+pub use mqtt::{Mqtt, MqttVersion};
+pub use mysql::Mysql;
+pub use postgres::Postgres;
+pub use proxy::{Proxy, ProxyKind, Tunnel};
+pub use proxy_protocol::{ProxyHeader, ProxyProtocol};
+pub use redis::Redis;
+pub use smtp::Smtp;
+pub use ssh::Ssh;
+pub use syn::PingSyn;
+pub use tcp_info::TcpInfo;
+pub use udp::PingUdp;
+pub use unix::PingUnix;
+pub use websocket::Websocket;
 
 pub trait Pinger {
-    fn ping(&self) -> Option<std::io::Error>;
+    fn ping(&self) -> Ping;
+}
+
+/// The outcome of a single ping.
+#[derive(Debug, Default)]
+pub struct Ping {
+    pub err: Option<PingError>,
+    /// The local port the connection was attempted from, when known.
+    pub source_port: Option<u16>,
+    /// The time taken to establish the connection, when the pinger measures
+    /// it apart from what it does with the connection afterwards. Through a
+    /// proxy, it lasts until the tunnel is open, and with TCP Fast Open until
+    /// the SYN sent along with the request is answered.
+    pub connect_time: Option<Duration>,
+    /// The time taken to connect to the proxy, when the probe goes through one.
+    pub proxy_time: Option<Duration>,
+    /// What the server sent first, when the banner is read.
+    pub banner: Option<Response>,
+    /// What the server answered to the request, when one is sent.
+    pub response: Option<Response>,
+    /// How the server identified itself, when the protocol tells it.
+    pub server: Option<String>,
+    /// Replies received again for a probe already answered.
+    pub duplicate_replies: u32,
+    /// Replies received for earlier probes, after they timed out.
+    pub out_of_order_replies: u32,
+    /// The TCP_INFO of the connection right after it was established, when
+    /// it is read.
+    pub tcp_info: Option<TcpInfo>,
+    /// The TCP_INFO of the connection after the data was exchanged, when it
+    /// is read and any was.
+    pub exchange_tcp_info: Option<TcpInfo>,
+}
+
+/// Data sent by the server once the connection is established.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub data: Vec<u8>,
+    /// The time between the connection, or the request, and the first byte of data.
+    pub time: Duration,
+}
+
+/// Makes data received from the network printable on a single line.
+pub fn sanitize(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches(['\r', '\n'])
+        .chars()
+        .flat_map(|c| match c.is_control() {
+            true => c.escape_default().collect::<Vec<_>>(),
+            false => vec![c],
+        })
+        .collect()
+}
+
+/// Why a ping failed.
+#[derive(Debug)]
+pub enum PingError {
+    /// The connection could not be established.
+    Connect(io::Error),
+    /// The server closed the connection before the end of the hold time.
+    EarlyClose {
+        after: Duration,
+        err: Option<io::Error>,
+    },
+    /// The request could not be sent.
+    Send(io::Error),
+    /// The server sent nothing after the connection was established.
+    NoResponse(io::Error),
+    /// The server sent data not matching the expected one.
+    UnexpectedResponse(Vec<u8>),
+    /// The server accepted the connection but failed the protocol check.
+    Protocol {
+        protocol: &'static str,
+        reason: String,
+    },
+    /// The proxy could not be reached or did not open the tunnel.
+    Proxy(String),
+    /// An ICMP port unreachable came back instead of a UDP reply.
+    PortUnreachable(io::Error),
+}
+
+impl PingError {
+    pub fn protocol(protocol: &'static str, reason: impl Into<String>) -> Self {
+        PingError::Protocol {
+            protocol,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn proxy(reason: impl Into<String>) -> Self {
+        PingError::Proxy(reason.into())
+    }
+
+    /// Whether the server accepted the connection but then did not speak
+    /// the protocol as expected, as opposed to failing at the TCP level.
+    pub fn is_protocol(&self) -> bool {
+        matches!(
+            self,
+            PingError::NoResponse(_)
+                | PingError::UnexpectedResponse(_)
+                | PingError::Protocol { .. }
+        )
+    }
+}
+
+impl fmt::Display for PingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingError::Connect(e) => write!(f, "{e}"),
+            PingError::EarlyClose { after, err: None } => {
+                write!(f, "closed by the server after {} ms", after.as_millis())
+            }
+            PingError::EarlyClose {
+                after,
+                err: Some(e),
+            } => write!(f, "dropped after {} ms: {e}", after.as_millis()),
+            PingError::Send(e) => write!(f, "refused the request: {e}"),
+            PingError::NoResponse(e) => write!(f, "sent nothing: {e}"),
+            PingError::UnexpectedResponse(data) => {
+                write!(f, "sent unexpected data \"{}\"", sanitize(data))
+            }
+            PingError::Protocol { protocol, reason } => {
+                write!(f, "failed the {protocol} check: {reason}")
+            }
+            PingError::Proxy(reason) => write!(f, "failed through the proxy: {reason}"),
+            PingError::PortUnreachable(e) => write!(f, "port unreachable: {e}"),
+        }
+    }
+}
+
+impl From<io::Error> for PingError {
+    fn from(e: io::Error) -> Self {
+        PingError::Connect(e)
+    }
+}
+
+/// Source ports used in turn by consecutive probes.
+#[derive(Clone, Debug)]
+pub struct SourcePorts {
+    range: RangeInclusive<u16>,
+    next: Cell<u16>,
+}
+
+impl SourcePorts {
+    pub fn new(range: RangeInclusive<u16>) -> SourcePorts {
+        let next = Cell::new(*range.start());
+        SourcePorts { range, next }
+    }
+
+    fn next(&self) -> u16 {
+        let port = self.next.get();
+        self.next.set(if port >= *self.range.end() {
+            *self.range.start()
+        } else {
+            port + 1
+        });
+        port
+    }
+}
+
+/// Where the probe connections originate from. By default the kernel picks
+/// the source address, the source port and the outgoing interface.
+#[derive(Clone, Debug, Default)]
+pub struct Bind {
+    pub source: Option<IpAddr>,
+    pub interface: Option<String>,
+    pub source_ports: Option<SourcePorts>,
+    /// The TTL, or hop limit on IPv6, of the outgoing packets.
+    pub ttl: Option<u32>,
+    pub options: SocketOptions,
+}
+
+/// The socket options of the probes, to see how the network in between
+/// treats them. Unlike the rest of `Bind`, an option the system refuses does
+/// not fail the probes: it is reported by `Bind::check` and left unset.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Whether the first data of the probe is sent in the SYN, with TCP Fast
+    /// Open. Without a cookie from the server, a regular SYN asks for one.
+    pub fast_open: bool,
+    pub nodelay: bool,
+    /// The maximum segment size announced in the SYN.
+    pub mss: Option<u32>,
+    /// The TOS byte, or traffic class on IPv6.
+    pub tos: Option<u32>,
+    /// The congestion control algorithm, like `cubic` or `bbr`.
+    pub congestion: Option<String>,
+}
+
+impl Bind {
+    /// Creates a socket able to connect to `target`, bound to the source
+    /// address, source port and interface.
+    pub fn socket(&self, target: &SocketAddr, source_port: Option<u16>) -> io::Result<Socket> {
+        self.new_socket(target, source_port, Type::STREAM, Protocol::TCP)
+    }
+
+    /// Creates a UDP socket bound like the ones of `socket`.
+    pub fn datagram_socket(
+        &self,
+        target: &SocketAddr,
+        source_port: Option<u16>,
+    ) -> io::Result<Socket> {
+        self.new_socket(target, source_port, Type::DGRAM, Protocol::UDP)
+    }
+
+    fn new_socket(
+        &self,
+        target: &SocketAddr,
+        source_port: Option<u16>,
+        ty: Type,
+        protocol: Protocol,
+    ) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(*target), ty, Some(protocol))?;
+        if let Some(interface) = &self.interface {
+            bind_device(&socket, interface)?;
+        }
+        if let Some(ttl) = self.ttl {
+            match target {
+                SocketAddr::V4(_) => socket.set_ttl(ttl)?,
+                SocketAddr::V6(_) => socket.set_unicast_hops_v6(ttl)?,
+            }
+        }
+        self.set_options(&socket, target, ty);
+        if let Some(source) = self.source {
+            if source.is_ipv4() != target.is_ipv4() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "source address {source} and target {target} are of different families"
+                    ),
+                ));
+            }
+        }
+        if self.source.is_some() || source_port.is_some() {
+            let source = self.source.unwrap_or(match target {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            });
+            // consecutive probes from the same port find it still in TIME_WAIT
+            socket.set_reuse_address(source_port.is_some())?;
+            socket.bind(&SocketAddr::new(source, source_port.unwrap_or(0)).into())?;
+        }
+        Ok(socket)
+    }
+
+    /// Sets the socket options, returning each of them with the value read
+    /// back from the socket, or with the reason it was refused.
+    fn set_options(
+        &self,
+        socket: &Socket,
+        target: &SocketAddr,
+        ty: Type,
+    ) -> Vec<(&'static str, io::Result<String>)> {
+        let options = &self.options;
+        let mut set = Vec::new();
+        if let Some(tos) = options.tos {
+            let tos = match target {
+                SocketAddr::V4(_) => socket.set_tos(tos).and_then(|_| socket.tos()),
+                SocketAddr::V6(_) => socket.set_tclass_v6(tos).and_then(|_| socket.tclass_v6()),
+            };
+            set.push(("tos", tos.map(|tos| format!("{tos:#04x}"))));
+        }
+        // the rest only concern TCP
+        if ty != Type::STREAM {
+            return set;
+        }
+        if options.nodelay {
+            let nodelay = socket.set_nodelay(true).and_then(|_| socket.nodelay());
+            set.push(("nodelay", nodelay.map(|on| on.to_string())));
+        }
+        if let Some(mss) = options.mss {
+            let mss = socket.set_mss(mss).and_then(|_| socket.mss());
+            set.push(("mss", mss.map(|mss| mss.to_string())));
+        }
+        if let Some(congestion) = &options.congestion {
+            set.push(("congestion", set_congestion(socket, congestion)));
+        }
+        if options.fast_open {
+            let fast_open = set_fast_open(socket).map(|_| true.to_string());
+            set.push(("tfo", fast_open));
+        }
+        set
+    }
+
+    /// Checks that the source address is local, the source port can be used
+    /// and the interface exists, so that a misconfiguration is reported once
+    /// instead of failing every probe. Returns the socket options, each with
+    /// the value in effect or the reason it was refused.
+    pub fn check(&self, target: &SocketAddr) -> io::Result<Vec<String>> {
+        let source_port = self.source_ports.as_ref().map(|ports| *ports.range.start());
+        let socket = self.socket(target, source_port)?;
+        let mut options = Vec::new();
+        if self.ttl.is_some() {
+            let ttl = match target {
+                SocketAddr::V4(_) => socket.ttl(),
+                SocketAddr::V6(_) => socket.unicast_hops_v6(),
+            };
+            options.push(("ttl", ttl.map(|ttl| ttl.to_string())));
+        }
+        options.extend(self.set_options(&socket, target, Type::STREAM));
+        Ok(options
+            .into_iter()
+            .map(|(name, value)| match value {
+                Ok(value) => format!("{name}={value}"),
+                Err(e) => format!("{name} refused ({e})"),
+            })
+            .collect())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_congestion(socket: &Socket, congestion: &str) -> io::Result<String> {
+    socket.set_tcp_congestion(congestion.as_bytes())?;
+    let congestion = socket.tcp_congestion()?;
+    Ok(String::from_utf8_lossy(&congestion)
+        .trim_end_matches('\0')
+        .to_owned())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_congestion(_: &Socket, _: &str) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "choosing the congestion control is only supported on Linux",
+    ))
+}
+
+/// Defers the SYN of the connection to the first write, which it carries when
+/// the server gave a TCP Fast Open cookie before.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_fast_open(socket: &Socket) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    // SAFETY: the option value points to a c_int of the given length
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            &on as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_fast_open(_: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP Fast Open is only supported on Linux",
+    ))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_device(_: &Socket, _: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface is only supported on Linux",
+    ))
+}
+
+/// Opens a connection to `target`, returning it along with the source port it
+/// was attempted from.
+fn connect(
+    target: &SocketAddr,
+    timeout: Option<Duration>,
+    bind: &Bind,
+) -> (Option<u16>, io::Result<TcpStream>) {
+    let source_port = bind.source_ports.as_ref().map(SourcePorts::next);
+    let socket = match bind.socket(target, source_port) {
+        Ok(socket) => socket,
+        Err(e) => return (source_port, Err(e)),
+    };
+    let result = match timeout {
+        Some(timeout) => start_connect(&socket, target).and_then(|connected| {
+            if !connected {
+                wait_connect(&socket, Some(Instant::now() + timeout), None)?;
+            }
+            socket.set_nonblocking(false)
+        }),
+        None => socket.connect(&(*target).into()),
+    };
+    let source_port = source_port.or_else(|| {
+        socket
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_socket())
+            .map(|addr| addr.port())
+            .filter(|port| *port != 0)
+    });
+    (source_port, result.map(|_| socket.into()))
+}
+
+/// Starts connecting `socket` to `target` without blocking, returning whether
+/// the connection was established at once.
+pub(crate) fn start_connect(socket: &Socket, target: &SocketAddr) -> io::Result<bool> {
+    socket.set_nonblocking(true)?;
+    match socket.connect(&(*target).into()) {
+        Ok(()) => Ok(true),
+        Err(e)
+            if e.raw_os_error() == Some(libc::EINPROGRESS)
+                || e.kind() == io::ErrorKind::WouldBlock =>
+        {
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// What ended the wait of `wait_connect`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Wake {
+    Connected,
+    /// The other file descriptor has data to read.
+    Readable,
+}
+
+/// Waits for the connection started by `start_connect` until `deadline`, if
+/// any, or until `other` has data to read, which is checked first. Fails with
+/// the error of the connection, or `TimedOut` at the deadline.
+pub(crate) fn wait_connect(
+    socket: &Socket,
+    deadline: Option<Instant>,
+    other: Option<BorrowedFd>,
+) -> io::Result<Wake> {
+    let mut fds = vec![libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: libc::POLLOUT,
+        revents: 0,
+    }];
+    if let Some(other) = other {
+        fds.push(libc::pollfd {
+            fd: other.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+    }
+    loop {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                // rounded up, so that the deadline is not polled for again and again
+                remaining
+                    .as_micros()
+                    .div_ceil(1000)
+                    .min(libc::c_int::MAX as u128) as libc::c_int
+            }
+            None => -1,
+        };
+        // SAFETY: fds points to as many pollfd as given
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if fds.get(1).is_some_and(|other| other.revents != 0) {
+            return Ok(Wake::Readable);
+        }
+        if fds[0].revents != 0 {
+            return match socket.take_error()? {
+                Some(e) => Err(e),
+                None => Ok(Wake::Connected),
+            };
+        }
+    }
+}
+
+/// How the probe connection is closed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Close {
+    /// The socket is closed without waiting for the server.
+    #[default]
+    Drop,
+    /// A FIN is sent and the FIN of the server is awaited.
+    Graceful,
+    /// The connection is reset, with SO_LINGER set to 0.
+    Reset,
+}
+
+/// How long the server may pause within its data before the read stops,
+/// when nothing tells where the data ends.
+const RESPONSE_IDLE: Duration = Duration::from_millis(100);
+
+/// How the data sent by the server is read and checked.
+#[derive(Clone, Debug)]
+pub struct ResponseCheck {
+    /// The most bytes read. The read stops earlier as soon as the data
+    /// matches `expect` or, without it, at the end of a line or once the
+    /// server pauses for `RESPONSE_IDLE`.
+    pub max_len: usize,
+    /// How long to wait for the data.
+    pub timeout: Duration,
+    /// What the data should match for the probe to succeed.
+    pub expect: Option<Regex>,
+}
+
+impl ResponseCheck {
+    fn read(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let idle = match self.expect {
+            Some(_) => None,
+            None => Some(RESPONSE_IDLE),
+        };
+        let response = read_until_idle(stream, self.timeout, idle, self.max_len, |data| {
+            self.is_complete(data)
+        })?;
+        if self
+            .expect
+            .as_ref()
+            .is_some_and(|expect| !expect.is_match(&response.data))
+        {
+            return Err(PingError::UnexpectedResponse(response.data));
+        }
+        Ok(response)
+    }
+
+    fn is_complete(&self, data: &[u8]) -> bool {
+        !data.is_empty()
+            && (data.ends_with(b"\n")
+                || self
+                    .expect
+                    .as_ref()
+                    .is_some_and(|expect| expect.is_match(data)))
+    }
+}
+
+/// Reads until `is_complete` holds for the data, `max_len` bytes are read,
+/// the server closes the connection or `timeout` expires, failing only when
+/// nothing was read.
+fn read_until(
+    stream: &mut TcpStream,
+    timeout: Duration,
+    max_len: usize,
+    is_complete: impl Fn(&[u8]) -> bool,
+) -> Result<Response, PingError> {
+    read_until_idle(stream, timeout, None, max_len, is_complete)
+}
+
+/// Reads as `read_until` does, but also stops once some data was read and
+/// the server then sends nothing for `idle`.
+fn read_until_idle(
+    stream: &mut TcpStream,
+    timeout: Duration,
+    idle: Option<Duration>,
+    max_len: usize,
+    is_complete: impl Fn(&[u8]) -> bool,
+) -> Result<Response, PingError> {
+    let start = Instant::now();
+    let mut data = Vec::new();
+    let mut time = None;
+    let mut closed = false;
+    let mut buf = [0; 512];
+    while data.len() < max_len && !is_complete(&data) {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        let wait = match idle {
+            Some(idle) if !data.is_empty() => remaining.min(idle),
+            _ => remaining,
+        };
+        stream
+            .set_read_timeout(Some(wait))
+            .map_err(PingError::NoResponse)?;
+        let len = buf.len().min(max_len - data.len());
+        match stream.read(&mut buf[..len]) {
+            Ok(0) => {
+                closed = true;
+                break;
+            }
+            Ok(n) => {
+                time.get_or_insert(start.elapsed());
+                data.extend_from_slice(&buf[..n]);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) if data.is_empty() => return Err(PingError::NoResponse(e)),
+            Err(_) => break,
+        }
+    }
+    match time {
+        Some(time) => Ok(Response { data, time }),
+        None => Err(PingError::NoResponse(match closed {
+            true => io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"),
+            false => io::Error::new(io::ErrorKind::TimedOut, "timed out"),
+        })),
+    }
+}
+
+/// A protocol spoken right after connecting, to check that the server
+/// application is healthy and not only accepting connections.
+pub trait Handshake {
+    /// Talks to the server, returning its answer with the time the whole
+    /// exchange took.
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError>;
+
+    /// How the server identified itself in its answer, if the protocol tells it.
+    fn server(&self, _response: &Response) -> Option<String> {
+        None
+    }
+}
+
+/// What a probe does with the connection once it is established.
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    /// The tunnel asked to the proxy the probe connects to, if it goes through one.
+    pub tunnel: Option<Tunnel>,
+    /// The PROXY protocol header sent before anything else, if any.
+    pub proxy_header: Option<ProxyHeader>,
+    /// How the banner the server sends first is read, if it is.
+    pub banner: Option<ResponseCheck>,
+    /// The data sent to the server, after the banner if it is read.
+    pub request: Option<Vec<u8>>,
+    /// How the answer to the request is read, if it is.
+    pub response: Option<ResponseCheck>,
+    /// How long the connection is kept open before closing it.
+    pub hold: Option<Duration>,
+    pub close: Close,
+    /// How long a graceful close waits for the FIN of the server.
+    pub close_timeout: Duration,
+    /// Whether the TCP_INFO of the connection is read after connecting and
+    /// after the exchange.
+    pub tcp_info: bool,
+}
+
+/// How the connection handed to the session was opened.
+#[derive(Clone, Copy)]
+struct Connect {
+    /// Whether the SYN waits for the request, with TCP Fast Open.
+    fast_open: bool,
+    timeout: Option<Duration>,
+}
+
+impl Session {
+    fn run(
+        &self,
+        mut stream: TcpStream,
+        connect: Connect,
+        handshake: Option<&dyn Handshake>,
+        ping: &mut Ping,
+    ) -> Result<(), PingError> {
+        let result = self.exchange(&mut stream, connect, handshake, ping);
+        if self.tcp_info && self.exchanges(handshake) {
+            ping.exchange_tcp_info = TcpInfo::read(&stream).ok();
+        }
+        self.close(stream);
+        result
+    }
+
+    /// Whether the probe sends or reads anything on the connection.
+    fn exchanges(&self, handshake: Option<&dyn Handshake>) -> bool {
+        self.tunnel.is_some()
+            || self.proxy_header.is_some()
+            || handshake.is_some()
+            || self.banner.is_some()
+            || self.request.is_some()
+    }
+
+    fn exchange(
+        &self,
+        stream: &mut TcpStream,
+        connect: Connect,
+        handshake: Option<&dyn Handshake>,
+        ping: &mut Ping,
+    ) -> Result<(), PingError> {
+        if let Some(tunnel) = &self.tunnel {
+            let start = Instant::now();
+            tunnel.open(stream)?;
+            ping.proxy_time = ping.connect_time;
+            ping.connect_time = ping.connect_time.map(|t| t + start.elapsed());
+        }
+        if let Some(proxy_header) = &self.proxy_header {
+            proxy_header.send(stream)?;
+        }
+        if let Some(handshake) = handshake {
+            let response = handshake.run(stream)?;
+            ping.server = handshake.server(&response);
+            ping.response = Some(response);
+        }
+        if let Some(banner) = &self.banner {
+            ping.banner = Some(banner.read(stream)?);
+        }
+        if let Some(request) = &self.request {
+            match connect.fast_open {
+                true => self.send_fast_open(stream, request, connect.timeout, ping)?,
+                false => stream.write_all(request).map_err(PingError::Send)?,
+            }
+        }
+        if let Some(response) = &self.response {
+            ping.response = Some(response.read(stream)?);
+        }
+        match self.hold {
+            Some(hold) => hold_open(stream, hold),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the request along with the SYN, which the connect deferred, and
+    /// times the connection until the SYN is answered.
+    fn send_fast_open(
+        &self,
+        stream: &mut TcpStream,
+        request: &[u8],
+        timeout: Option<Duration>,
+        ping: &mut Ping,
+    ) -> Result<(), PingError> {
+        let start = Instant::now();
+        // the write may either wait for the connection or return as soon as
+        // the SYN carrying the request is sent
+        stream
+            .set_write_timeout(timeout)
+            .map_err(PingError::Connect)?;
+        stream.write_all(request).map_err(PingError::Connect)?;
+        wait_connect(
+            &SockRef::from(&*stream),
+            timeout.map(|timeout| start + timeout),
+            None,
+        )
+        .map_err(PingError::Connect)?;
+        ping.connect_time = ping.connect_time.map(|t| t + start.elapsed());
+        if self.tcp_info {
+            ping.tcp_info = TcpInfo::read(stream).ok();
+        }
+        Ok(())
+    }
+
+    fn close(&self, mut stream: TcpStream) {
+        match self.close {
+            Close::Drop => {}
+            Close::Graceful => {
+                _ = stream.shutdown(Shutdown::Write);
+                // the server may keep sending, so the wait is bounded as a whole
+                let start = Instant::now();
+                let mut buf = [0; 512];
+                loop {
+                    let remaining = self.close_timeout.saturating_sub(start.elapsed());
+                    if remaining.is_zero() || stream.set_read_timeout(Some(remaining)).is_err() {
+                        break;
+                    }
+                    match stream.read(&mut buf) {
+                        Ok(1..) => {}
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        _ => break,
+                    }
+                }
+            }
+            Close::Reset => _ = SockRef::from(&stream).set_linger(Some(Duration::ZERO)),
+        }
+    }
+}
+
+/// Keeps the connection open for `hold`, failing if the server closes it
+/// earlier. Whatever the server sends in the meantime is discarded.
+fn hold_open(stream: &mut TcpStream, hold: Duration) -> Result<(), PingError> {
+    let start = Instant::now();
+    // the connection is already established, so any failure ends it early
+    let closed = |err| PingError::EarlyClose {
+        after: start.elapsed(),
+        err,
+    };
+    let mut buf = [0; 512];
+    loop {
+        let remaining = hold.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Ok(());
+        }
+        stream
+            .set_read_timeout(Some(remaining))
+            .map_err(|e| closed(Some(e)))?;
+        match stream.read(&mut buf) {
+            Ok(0) => return Err(closed(None)),
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(closed(Some(e))),
+        }
+    }
+}
+
+fn ping(
+    target: &SocketAddr,
+    timeout: Option<Duration>,
+    bind: &Bind,
+    session: &Session,
+    handshake: Option<&dyn Handshake>,
+) -> Ping {
+    let start = Instant::now();
+    let (source_port, stream) = connect(target, timeout, bind);
+    let mut ping = Ping {
+        source_port,
+        connect_time: Some(start.elapsed()),
+        ..Default::default()
+    };
+    // with TCP Fast Open, the connect returns before the SYN is even sent
+    let connect = Connect {
+        fast_open: bind.options.fast_open,
+        timeout,
+    };
+    ping.err = match stream {
+        Ok(stream) => {
+            if session.tcp_info && !connect.fast_open {
+                ping.tcp_info = TcpInfo::read(&stream).ok();
+            }
+            session.run(stream, connect, handshake, &mut ping).err()
+        }
+        Err(e) if session.tunnel.is_some() => Some(PingError::Proxy(format!(
+            "cannot connect to the proxy: {e}"
+        ))),
+        Err(e) => Some(e.into()),
+    };
+    ping
 }
 
 pub struct PingTimeout {
     pub socket: SocketAddr,
     pub conn_timeout: std::time::Duration,
+    pub bind: Bind,
+    pub session: Session,
 }
 
 impl Pinger for PingTimeout {
-    fn ping(&self) -> Option<std::io::Error> {
-        TcpStream::connect_timeout(&self.socket, self.conn_timeout).err()
+    fn ping(&self) -> Ping {
+        ping(
+            &self.socket,
+            Some(self.conn_timeout),
+            &self.bind,
+            &self.session,
+            None,
+        )
     }
 }
 
 pub struct PingWithoutTimeout {
     pub socket: SocketAddr,
+    pub bind: Bind,
+    pub session: Session,
 }
 
 impl Pinger for PingWithoutTimeout {
-    fn ping(&self) -> Option<std::io::Error> {
-        TcpStream::connect(self.socket).err()
+    fn ping(&self) -> Ping {
+        ping(&self.socket, None, &self.bind, &self.session, None)
+    }
+}
+
+/// Connects and then checks that the server speaks the protocol of `handshake`.
+pub struct PingProtocol {
+    pub socket: SocketAddr,
+    pub conn_timeout: Option<Duration>,
+    pub bind: Bind,
+    pub session: Session,
+    pub handshake: Box<dyn Handshake>,
+}
+
+impl Pinger for PingProtocol {
+    fn ping(&self) -> Ping {
+        ping(
+            &self.socket,
+            self.conn_timeout,
+            &self.bind,
+            &self.session,
+            Some(self.handshake.as_ref()),
+        )
+    }
+}
+
+/// Probes a fake server on the loopback, played by `serve` on the accepted
+/// connection, returning the outcome along with what `serve` returned.
+#[cfg(test)]
+fn ping_fake_server<T: Send>(
+    session: &Session,
+    handshake: Option<&dyn Handshake>,
+    serve: impl FnOnce(TcpStream) -> T + Send,
+) -> (Ping, T) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let target = listener.local_addr().unwrap();
+    std::thread::scope(|scope| {
+        let server = scope.spawn(move || serve(listener.accept().unwrap().0));
+        let ping = ping(&target, None, &Bind::default(), session, handshake);
+        (ping, server.join().unwrap())
+    })
+}
+
+/// Probes a fake server sending `greeting` upon accepting, returning the
+/// outcome along with what the client sent.
+#[cfg(test)]
+fn ping_greeting(handshake: &dyn Handshake, greeting: &[u8]) -> (Ping, Vec<u8>) {
+    ping_fake_server(&Session::default(), Some(handshake), |mut stream| {
+        stream.write_all(greeting).unwrap();
+        let mut sent = Vec::new();
+        _ = stream.read_to_end(&mut sent);
+        sent
+    })
+}
+
+/// Probes a fake server answering `answer` to the first request, returning
+/// the outcome along with the request.
+#[cfg(test)]
+fn ping_answer(handshake: &dyn Handshake, answer: &[u8]) -> (Ping, Vec<u8>) {
+    ping_fake_server(&Session::default(), Some(handshake), |mut stream| {
+        let mut request = vec![0; 1024];
+        let n = stream.read(&mut request).unwrap();
+        request.truncate(n);
+        stream.write_all(answer).unwrap();
+        request
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        os::fd::AsRawFd,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use regex::bytes::Regex;
+    use socket2::{Domain, Socket, Type};
+
+    use crate::pinger::{
+        sanitize, set_fast_open, Bind, Close, Connect, Ping, PingError, PingTimeout,
+        PingWithoutTimeout, Pinger, ResponseCheck, Session, SocketOptions, SourcePorts,
+    };
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn test_ping_from_source() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pinger = PingTimeout {
+            socket: listener.local_addr().unwrap(),
+            conn_timeout: Duration::from_secs(1),
+            bind: Bind {
+                source: Some("127.0.0.2".parse().unwrap()),
+                ..Default::default()
+            },
+            session: Session::default(),
+        };
+        assert!(pinger.ping().err.is_none());
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.ip(), "127.0.0.2".parse::<std::net::IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_source_not_local() {
+        let bind = Bind {
+            source: Some("192.0.2.1".parse().unwrap()),
+            ..Default::default()
+        };
+        let target: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        assert_eq!(
+            bind.check(&target).unwrap_err().kind(),
+            io::ErrorKind::AddrNotAvailable
+        );
+    }
+
+    #[test]
+    fn test_source_family_mismatch() {
+        let bind = Bind {
+            source: Some("127.0.0.1".parse().unwrap()),
+            ..Default::default()
+        };
+        let target: SocketAddr = "[::1]:80".parse().unwrap();
+        assert_eq!(
+            bind.check(&target).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_unknown_interface() {
+        let bind = Bind {
+            interface: Some("rustcping-none".to_owned()),
+            ..Default::default()
+        };
+        let target: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        assert!(bind.check(&target).is_err());
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let bind = Bind {
+            ttl: Some(5),
+            options: SocketOptions {
+                nodelay: true,
+                mss: Some(1200),
+                // the ECN bits are left to the kernel
+                tos: Some(0x11),
+                congestion: Some("rustcping-none".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let options = bind.check(&listener.local_addr().unwrap()).unwrap();
+        assert_eq!(
+            options[..4],
+            ["ttl=5", "tos=0x10", "nodelay=true", "mss=1200"]
+        );
+        assert!(options[4].starts_with("congestion refused ("));
+        // the refused option does not fail the probes
+        let pinger = PingTimeout {
+            socket: listener.local_addr().unwrap(),
+            conn_timeout: Duration::from_secs(1),
+            bind,
+            session: Session::default(),
+        };
+        assert!(pinger.ping().err.is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_tcp_info() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pinger = |request: Option<Vec<u8>>| PingTimeout {
+            socket: listener.local_addr().unwrap(),
+            conn_timeout: Duration::from_secs(1),
+            bind: Bind::default(),
+            session: Session {
+                request,
+                tcp_info: true,
+                ..Default::default()
+            },
+        };
+        let ping = pinger(None).ping();
+        assert!(ping.err.is_none());
+        assert_eq!(ping.tcp_info.unwrap().retransmits, 0);
+        assert!(ping.exchange_tcp_info.is_none());
+        let ping = pinger(Some(b"PING\r\n".to_vec())).ping();
+        assert!(ping.tcp_info.is_some());
+        assert!(ping.exchange_tcp_info.is_some());
+    }
+
+    /// The accept queue of the listener is full when the probe connects, so
+    /// its SYN is dropped and only the retransmitted one is answered. Without
+    /// a cookie, the SYN waits for the request only when told so.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fast_open_connect_time() {
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        socket
+            .bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into())
+            .unwrap();
+        socket.listen(0).unwrap();
+        let listener: TcpListener = socket.into();
+        let target = listener.local_addr().unwrap();
+        let _queued = TcpStream::connect(target).unwrap();
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            let _queued = listener.accept().unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).unwrap();
+            request
+        });
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        set_fast_open(&socket).unwrap();
+        let on: libc::c_int = 1;
+        // SAFETY: the option value points to a c_int of the given length
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_FASTOPEN_NO_COOKIE,
+                &on as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(result, 0);
+        let start = Instant::now();
+        socket.connect(&target.into()).unwrap();
+        let mut ping = Ping {
+            connect_time: Some(start.elapsed()),
+            ..Default::default()
+        };
+        let session = Session {
+            request: Some(b"PING".to_vec()),
+            tcp_info: true,
+            ..Default::default()
+        };
+        let connect = Connect {
+            fast_open: true,
+            timeout: Some(Duration::from_secs(5)),
+        };
+        let result = session.run(socket.into(), connect, None, &mut ping);
+        assert!(result.is_ok(), "{result:?}");
+        assert!(ping.connect_time.unwrap() > Duration::from_millis(900));
+        assert!(ping.tcp_info.unwrap().retransmits > 0);
+        assert_eq!(&server.join().unwrap(), b"PING");
+    }
+
+    #[test]
+    fn test_source_port_fixed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let source_port = free_port();
+        let pinger = PingTimeout {
+            socket: listener.local_addr().unwrap(),
+            conn_timeout: Duration::from_secs(1),
+            bind: Bind {
+                source_ports: Some(SourcePorts::new(source_port..=source_port)),
+                ..Default::default()
+            },
+            // as by default with --source-port, since closing first would
+            // leave the port in TIME_WAIT
+            session: Session {
+                close: Close::Reset,
+                ..Default::default()
+            },
+        };
+        for _ in 0..2 {
+            let ping = pinger.ping();
+            assert!(ping.err.is_none());
+            assert_eq!(ping.source_port, Some(source_port));
+            let (_, peer) = listener.accept().unwrap();
+            assert_eq!(peer.port(), source_port);
+        }
+    }
+
+    #[test]
+    fn test_source_port_range_cycles() {
+        let ports = SourcePorts::new(40000..=40002);
+        let used: Vec<u16> = (0..5).map(|_| ports.next()).collect();
+        assert_eq!(used, [40000, 40001, 40002, 40000, 40001]);
+    }
+
+    #[test]
+    fn test_source_port_kernel_chosen() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pinger = PingTimeout {
+            socket: listener.local_addr().unwrap(),
+            conn_timeout: Duration::from_secs(1),
+            bind: Bind::default(),
+            session: Session::default(),
+        };
+        let ping = pinger.ping();
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(ping.source_port, Some(peer.port()));
+    }
+
+    fn holding_pinger(listener: &TcpListener, hold: Duration) -> PingWithoutTimeout {
+        PingWithoutTimeout {
+            socket: listener.local_addr().unwrap(),
+            bind: Bind::default(),
+            session: Session {
+                hold: Some(hold),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_hold() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = thread::spawn({
+            let listener = listener.try_clone().unwrap();
+            move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream.read_to_end(&mut Vec::new()).unwrap();
+            }
+        });
+        let ping = holding_pinger(&listener, Duration::from_millis(200)).ping();
+        assert!(ping.err.is_none());
+        assert!(ping.connect_time.unwrap() < Duration::from_millis(200));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_hold_early_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = thread::spawn({
+            let listener = listener.try_clone().unwrap();
+            move || drop(listener.accept().unwrap())
+        });
+        let ping = holding_pinger(&listener, Duration::from_secs(5)).ping();
+        match ping.err {
+            Some(PingError::EarlyClose { after, .. }) => assert!(after < Duration::from_secs(5)),
+            other => panic!("expected an early close, got {other:?}"),
+        }
+        server.join().unwrap();
+    }
+
+    fn closing_pinger(listener: &TcpListener, close: Close) -> PingWithoutTimeout {
+        PingWithoutTimeout {
+            socket: listener.local_addr().unwrap(),
+            bind: Bind::default(),
+            session: Session {
+                close,
+                close_timeout: Duration::from_secs(1),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_close_reset() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(closing_pinger(&listener, Close::Reset).ping().err.is_none());
+        let (mut stream, _) = listener.accept().unwrap();
+        let err = stream.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn test_close_graceful_chatty_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = thread::spawn({
+            let listener = listener.try_clone().unwrap();
+            move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                // keeps sending faster than the close timeout
+                while stream.write_all(b"tick\n").is_ok() {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        });
+        let start = Instant::now();
+        assert!(closing_pinger(&listener, Close::Graceful)
+            .ping()
+            .err
+            .is_none());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_close_graceful() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = thread::spawn({
+            let listener = listener.try_clone().unwrap();
+            move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                // the client half-closes and waits for this side to close
+                assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+            }
+        });
+        assert!(closing_pinger(&listener, Close::Graceful)
+            .ping()
+            .err
+            .is_none());
+        server.join().unwrap();
+    }
+
+    fn banner_pinger(listener: &TcpListener, expect: Option<&str>) -> PingWithoutTimeout {
+        PingWithoutTimeout {
+            socket: listener.local_addr().unwrap(),
+            bind: Bind::default(),
+            session: Session {
+                banner: Some(ResponseCheck {
+                    max_len: 64,
+                    timeout: Duration::from_millis(500),
+                    expect: expect.map(|expect| Regex::new(expect).unwrap()),
+                }),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn serve_banner(listener: &TcpListener, banner: &'static [u8]) -> thread::JoinHandle<()> {
+        let listener = listener.try_clone().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(banner).unwrap();
+            _ = stream.read_to_end(&mut Vec::new());
+        })
+    }
+
+    #[test]
+    fn test_banner() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = serve_banner(&listener, b"SSH-2.0-OpenSSH_9.6\r\n");
+        let ping = banner_pinger(&listener, Some("^SSH-2\\.0-")).ping();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.banner.unwrap().data, b"SSH-2.0-OpenSSH_9.6\r\n");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_banner_split() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = thread::spawn({
+            let listener = listener.try_clone().unwrap();
+            move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream.write_all(b"SSH-2.0-").unwrap();
+                thread::sleep(Duration::from_millis(50));
+                stream.write_all(b"OpenSSH_9.6\r\n").unwrap();
+                _ = stream.read_to_end(&mut Vec::new());
+            }
+        });
+        let ping = banner_pinger(&listener, None).ping();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.banner.unwrap().data, b"SSH-2.0-OpenSSH_9.6\r\n");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_banner_max_len() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = serve_banner(&listener, &[b'a'; 100]);
+        let ping = banner_pinger(&listener, None).ping();
+        assert_eq!(ping.banner.unwrap().data.len(), 64);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_banner_unexpected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = serve_banner(&listener, b"220 smtp.example.com ESMTP\r\n");
+        match banner_pinger(&listener, Some("^SSH-")).ping().err {
+            Some(PingError::UnexpectedResponse(data)) => {
+                assert_eq!(data, b"220 smtp.example.com ESMTP\r\n")
+            }
+            other => panic!("expected an unexpected banner, got {other:?}"),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_banner_missing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = serve_banner(&listener, b"");
+        match banner_pinger(&listener, None).ping().err {
+            Some(PingError::NoResponse(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            other => panic!("expected no banner, got {other:?}"),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize(b"SSH-2.0-OpenSSH_9.6\r\n"), "SSH-2.0-OpenSSH_9.6");
+        assert_eq!(sanitize(b"a\x1b[31mb\tc\xff"), "a\\u{1b}[31mb\\tc\u{fffd}");
+    }
+
+    fn request_pinger(listener: &TcpListener, request: &[u8], expect: &str) -> PingWithoutTimeout {
+        PingWithoutTimeout {
+            socket: listener.local_addr().unwrap(),
+            bind: Bind::default(),
+            session: Session {
+                request: Some(request.to_vec()),
+                response: Some(ResponseCheck {
+                    max_len: 64,
+                    timeout: Duration::from_millis(500),
+                    expect: Some(Regex::new(expect).unwrap()),
+                }),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Answers every request with the reversed request.
+    fn serve_reversed(listener: &TcpListener) -> thread::JoinHandle<()> {
+        let listener = listener.try_clone().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 64];
+            let n = stream.read(&mut buf).unwrap();
+            buf[..n].reverse();
+            stream.write_all(&buf[..n]).unwrap();
+            _ = stream.read_to_end(&mut Vec::new());
+        })
+    }
+
+    #[test]
+    fn test_request_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = serve_reversed(&listener);
+        let ping = request_pinger(&listener, b"\x01\x02\xff", "(?-u)^\\xff\\x02\\x01$").ping();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"\xff\x02\x01");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_request_binary_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = serve_reversed(&listener);
+        let pinger = PingWithoutTimeout {
+            socket: listener.local_addr().unwrap(),
+            bind: Bind::default(),
+            session: Session {
+                request: Some(b"\x01\x02\xff".to_vec()),
+                response: Some(ResponseCheck {
+                    max_len: 64,
+                    timeout: Duration::from_secs(5),
+                    expect: None,
+                }),
+                ..Default::default()
+            },
+        };
+        // the server keeps the connection open, and no line ends the answer
+        let start = Instant::now();
+        let ping = pinger.ping();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"\xff\x02\x01");
+        assert!(start.elapsed() < Duration::from_secs(2));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_request_unexpected_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = serve_reversed(&listener);
+        match request_pinger(&listener, b"PING", "^PONG").ping().err {
+            Some(PingError::UnexpectedResponse(data)) => assert_eq!(data, b"GNIP"),
+            other => panic!("expected an unexpected response, got {other:?}"),
+        }
+        server.join().unwrap();
     }
 }
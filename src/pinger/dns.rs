@@ -0,0 +1,232 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::Write,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::{read_until, Handshake, PingError, Response};
+
+const RECORD_TYPES: &[(&str, u16)] = &[
+    ("A", 1),
+    ("NS", 2),
+    ("CNAME", 5),
+    ("SOA", 6),
+    ("PTR", 12),
+    ("MX", 15),
+    ("TXT", 16),
+    ("AAAA", 28),
+    ("SRV", 33),
+    ("ANY", 255),
+];
+
+const RCODES: &[&str] = &[
+    "NOERROR", "FORMERR", "SERVFAIL", "NXDOMAIN", "NOTIMP", "REFUSED",
+];
+
+const HEALTHY_RCODES: &[u8] = &[0, 3];
+const QR: u16 = 0x8000;
+const RD: u16 = 0x0100;
+const MAX_MESSAGE_LEN: usize = 2 + u16::MAX as usize;
+
+pub fn parse_record_type(value: &str) -> Result<u16, String> {
+    RECORD_TYPES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map(|(_, record_type)| *record_type)
+        .or_else(|| value.parse().ok())
+        .ok_or_else(|| format!("'{value}' is not a record type like A, AAAA, NS or a number"))
+}
+
+pub fn encode_name(name: &str) -> Result<Vec<u8>, String> {
+    let mut encoded = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            return Err(format!("'{label}' is longer than 63 bytes"));
+        }
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    if encoded.len() > 255 {
+        return Err("the name is longer than 255 bytes".to_owned());
+    }
+    Ok(encoded)
+}
+
+pub struct Dns {
+    pub timeout: Duration,
+    // The name queried, already checked by `encode_name`.
+    pub name: String,
+    pub record_type: u16,
+}
+
+impl Handshake for Dns {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let id = RandomState::new().build_hasher().finish() as u16;
+        let query = self.query(id)?;
+        let start = Instant::now();
+        stream.write_all(&query).map_err(PingError::Send)?;
+        let message = read_until(stream, self.timeout, MAX_MESSAGE_LEN, |data| {
+            message_len(data).is_some_and(|len| data.len() >= len)
+        })?;
+        let time = start.elapsed();
+        let Some(message) = message_len(&message.data).and_then(|len| message.data.get(2..len))
+        else {
+            return Err(PingError::protocol(
+                "dns",
+                format!("truncated response of {} bytes", message.data.len()),
+            ));
+        };
+        let field = |i: usize| u16::from_be_bytes([message[i], message[i + 1]]);
+        if message.len() < 12 {
+            return Err(PingError::protocol("dns", "response shorter than a header"));
+        }
+        if field(0) != id {
+            return Err(PingError::protocol(
+                "dns",
+                format!("response to query {} instead of {id}", field(0)),
+            ));
+        }
+        if field(2) & QR == 0 {
+            return Err(PingError::protocol(
+                "dns",
+                "sent a query instead of a response",
+            ));
+        }
+        let rcode = (field(2) & 0x000f) as u8;
+        let rcode_name = RCODES
+            .get(rcode as usize)
+            .map_or_else(|| format!("RCODE{rcode}"), |name| (*name).to_owned());
+        if !HEALTHY_RCODES.contains(&rcode) {
+            return Err(PingError::protocol("dns", rcode_name));
+        }
+        Ok(Response {
+            data: format!("{rcode_name} answers={}", field(6)).into_bytes(),
+            time,
+        })
+    }
+}
+
+impl Dns {
+    fn query(&self, id: u16) -> Result<Vec<u8>, PingError> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(&RD.to_be_bytes());
+        // one question, no answer, authority or additional records
+        message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        message
+            .extend(encode_name(&self.name).map_err(|reason| PingError::protocol("dns", reason))?);
+        message.extend_from_slice(&self.record_type.to_be_bytes());
+        // class IN
+        message.extend_from_slice(&[0, 1]);
+        let mut query = (message.len() as u16).to_be_bytes().to_vec();
+        query.extend(message);
+        Ok(query)
+    }
+}
+
+fn message_len(data: &[u8]) -> Option<usize> {
+    let len = data.get(..2)?;
+    Some(2 + u16::from_be_bytes([len[0], len[1]]) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use crate::pinger::{
+        dns::{encode_name, parse_record_type},
+        ping_fake_server, Dns, Ping, PingError, Session,
+    };
+
+    fn ping(answer: fn(&mut Vec<u8>)) -> Ping {
+        let dns = Dns {
+            timeout: Duration::from_millis(500),
+            name: "example.com.".to_owned(),
+            record_type: 28,
+        };
+        ping_fake_server(&Session::default(), Some(&dns), |mut stream| {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut query = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut query).unwrap();
+            // flags, counts and then the question for example.com AAAA IN
+            assert_eq!(&query[2..12], [1, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+            assert_eq!(&query[12..], b"\x07example\x03com\0\0\x1c\0\x01");
+            answer(&mut query);
+            let mut response = (query.len() as u16).to_be_bytes().to_vec();
+            response.extend(query);
+            stream.write_all(&response).unwrap();
+        })
+        .0
+    }
+
+    #[test]
+    fn test_response() {
+        let ping = ping(|message| {
+            message[2] |= 0x80;
+            message[7] = 1;
+        });
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"NOERROR answers=1");
+    }
+
+    #[test]
+    fn test_nxdomain() {
+        let ping = ping(|message| {
+            message[2] |= 0x80;
+            message[3] |= 3;
+        });
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+    }
+
+    #[test]
+    fn test_servfail() {
+        match ping(|message| {
+            message[2] |= 0x80;
+            message[3] |= 2;
+        })
+        .err
+        {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "dns");
+                assert_eq!(reason, "SERVFAIL");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_id_mismatch() {
+        let ping = ping(|message| {
+            message[2] |= 0x80;
+            message[0] = !message[0];
+        });
+        assert!(matches!(ping.err, Some(PingError::Protocol { .. })));
+    }
+
+    #[test]
+    fn test_not_a_response() {
+        assert!(matches!(ping(|_| {}).err, Some(PingError::Protocol { .. })));
+    }
+
+    #[test]
+    fn test_encode_name() {
+        assert_eq!(encode_name(".").unwrap(), [0]);
+        assert_eq!(encode_name("a.bc").unwrap(), b"\x01a\x02bc\0");
+        assert!(encode_name(&"a".repeat(64)).is_err());
+        assert!(encode_name(&["abc"; 70].join(".")).is_err());
+    }
+
+    #[test]
+    fn test_parse_record_type() {
+        assert_eq!(parse_record_type("aaaa"), Ok(28));
+        assert_eq!(parse_record_type("65"), Ok(65));
+        assert!(parse_record_type("BOGUS").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    io::Write,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::{
+    reply::{self, read_reply},
+    Handshake, PingError, Response,
+};
+
+pub struct Ftp {
+    pub timeout: Duration,
+}
+
+impl Handshake for Ftp {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let start = Instant::now();
+        let greeting = read_reply(stream, self.timeout, "ftp")?;
+        // 120 tells that the server is not ready yet
+        if greeting.code != 220 {
+            return Err(PingError::protocol(
+                "ftp",
+                format!("replied {} {}", greeting.code, greeting.text),
+            ));
+        }
+        let time = start.elapsed();
+        // the session ends anyway, this only spares an error in the server logs
+        _ = stream.write_all(b"QUIT\r\n");
+        Ok(Response {
+            data: greeting.response.data,
+            time,
+        })
+    }
+
+    fn server(&self, response: &Response) -> Option<String> {
+        Some(reply::text(&response.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::pinger::{ping_greeting, Ftp, PingError};
+
+    const FTP: Ftp = Ftp {
+        timeout: Duration::from_millis(500),
+    };
+
+    #[test]
+    fn test_greeting() {
+        let (ping, sent) = ping_greeting(&FTP, b"220-Welcome\r\n220 (vsFTPd 3.0.5)\r\n");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.server.unwrap(), "Welcome");
+        assert_eq!(sent, b"QUIT\r\n");
+    }
+
+    #[test]
+    fn test_not_ready() {
+        match ping_greeting(&FTP, b"120 Ready in 5 minutes\r\n").0.err {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "ftp");
+                assert_eq!(reason, "replied 120 Ready in 5 minutes");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_not_ftp() {
+        let (ping, _) = ping_greeting(&FTP, b"SSH-2.0-OpenSSH_9.6\r\n");
+        assert!(matches!(ping.err, Some(PingError::Protocol { .. })));
+    }
+}
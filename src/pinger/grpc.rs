@@ -0,0 +1,640 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+use super::{hpack, Handshake, PingError, Response};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const PATH: &str = "/grpc.health.v1.Health/Check";
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+const STREAM_ID: u32 = 1;
+const MAX_FRAME_LEN: usize = 16384;
+const SERVING_STATUSES: &[&str] = &["UNKNOWN", "SERVING", "NOT_SERVING", "SERVICE_UNKNOWN"];
+const GRPC_STATUSES: &[&str] = &[
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+const H2_ERRORS: &[&str] = &[
+    "NO_ERROR",
+    "PROTOCOL_ERROR",
+    "INTERNAL_ERROR",
+    "FLOW_CONTROL_ERROR",
+    "SETTINGS_TIMEOUT",
+    "STREAM_CLOSED",
+    "FRAME_SIZE_ERROR",
+    "REFUSED_STREAM",
+    "CANCEL",
+    "COMPRESSION_ERROR",
+    "CONNECT_ERROR",
+    "ENHANCE_YOUR_CALM",
+    "INADEQUATE_SECURITY",
+    "HTTP_1_1_REQUIRED",
+];
+
+pub struct Grpc {
+    pub timeout: Duration,
+    pub authority: String,
+    pub service: String,
+    pub tls: Option<Tls>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tls {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl Tls {
+    pub fn new(server_name: &str, verify: bool) -> Result<Tls, String> {
+        let server_name = ServerName::try_from(server_name.to_owned())
+            .map_err(|_| format!("'{server_name}' is not a valid TLS server name"))?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?;
+        let mut config = match verify {
+            true => builder
+                .with_root_certificates(RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                })
+                .with_no_client_auth(),
+            false => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+                .with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        Ok(Tls {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+}
+
+// Accepts any certificate, checking only the signatures of the handshake.
+#[derive(Debug)]
+struct NoVerifier(Arc<rustls::crypto::CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+impl Handshake for Grpc {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let start = Instant::now();
+        // the timeouts are set on a clone, as the TLS stream borrows the original
+        let socket = stream.try_clone().map_err(PingError::NoResponse)?;
+        let Some(tls) = &self.tls else {
+            return self.call(stream, &socket, start);
+        };
+        let mut conn = ClientConnection::new(tls.config.clone(), tls.server_name.clone())
+            .map_err(|e| PingError::protocol("grpc", format!("cannot start TLS: {e}")))?;
+        while conn.is_handshaking() {
+            set_timeout(&socket, self.timeout, start)?;
+            conn.complete_io(stream)
+                .map_err(|e| PingError::protocol("grpc", format!("TLS handshake failed: {e}")))?;
+        }
+        if let Some(alpn) = conn.alpn_protocol().filter(|alpn| *alpn != b"h2") {
+            return Err(PingError::protocol(
+                "grpc",
+                format!("negotiated {} instead of h2", String::from_utf8_lossy(alpn)),
+            ));
+        }
+        let result = self.call(&mut rustls::Stream::new(&mut conn, stream), &socket, start);
+        conn.send_close_notify();
+        _ = conn.complete_io(stream);
+        result
+    }
+}
+
+impl Grpc {
+    fn call(
+        &self,
+        io: &mut (impl Read + Write),
+        socket: &TcpStream,
+        start: Instant,
+    ) -> Result<Response, PingError> {
+        let scheme = match self.tls {
+            Some(_) => "https",
+            None => "http",
+        };
+        let headers = hpack::encode(&[
+            (":method", "POST"),
+            (":scheme", scheme),
+            (":path", PATH),
+            (":authority", &self.authority),
+            ("content-type", "application/grpc"),
+            ("te", "trailers"),
+            ("user-agent", "rustcping"),
+        ]);
+        let mut request = PREFACE.to_vec();
+        request.extend(frame(SETTINGS, 0, 0, &[]));
+        request.extend(frame(HEADERS, END_HEADERS, STREAM_ID, &headers));
+        request.extend(frame(DATA, END_STREAM, STREAM_ID, &message(&self.service)));
+        io.write_all(&request).map_err(PingError::Send)?;
+        let mut frames = Frames {
+            io,
+            socket,
+            timeout: self.timeout,
+            start,
+            buf: Vec::new(),
+        };
+        let call = frames.call()?;
+        // leave the connection cleanly, the server may log it otherwise
+        _ = frames.io.write_all(&frame(GOAWAY, 0, 0, &[0; 8]));
+        let time = start.elapsed();
+        match call.status.as_deref() {
+            Some("200") => {}
+            Some(status) => {
+                return Err(PingError::protocol("grpc", format!("HTTP status {status}")))
+            }
+            None => return Err(PingError::protocol("grpc", "sent no HTTP status")),
+        }
+        match call.grpc_status.as_deref() {
+            Some("0") => {}
+            Some(code) => {
+                let name = code
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|code| GRPC_STATUSES.get(code))
+                    .unwrap_or(&"");
+                let message = call
+                    .grpc_message
+                    .map_or("".to_owned(), |message| format!(": {message}"));
+                return Err(PingError::protocol(
+                    "grpc",
+                    format!("grpc-status {code} {name}{message}"),
+                ));
+            }
+            None => return Err(PingError::protocol("grpc", "sent no grpc-status")),
+        }
+        let status =
+            health_status(&call.message).map_err(|reason| PingError::protocol("grpc", reason))?;
+        match SERVING_STATUSES.get(status as usize) {
+            Some(&"SERVING") => Ok(Response {
+                data: b"SERVING".to_vec(),
+                time,
+            }),
+            Some(name) => Err(PingError::protocol("grpc", format!("status {name}"))),
+            None => Err(PingError::protocol("grpc", format!("status {status}"))),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Call {
+    status: Option<String>,
+    grpc_status: Option<String>,
+    grpc_message: Option<String>,
+    message: Vec<u8>,
+}
+
+// Just enough HTTP/2 for a single unary call on a blocking socket, which the
+// h2 crate would need an async runtime for. The request is sent whole at once,
+// well within the initial windows, so the WINDOW_UPDATEs of the server are not
+// waited for; the DATA received is given back to the windows of the server.
+struct Frames<'a, S> {
+    io: &'a mut S,
+    socket: &'a TcpStream,
+    timeout: Duration,
+    start: Instant,
+    buf: Vec<u8>,
+}
+
+impl<S: Read + Write> Frames<'_, S> {
+    // Reads the frames until the server ends the stream of the call,
+    // answering the SETTINGS and PING frames on the way.
+    fn call(&mut self) -> Result<Call, PingError> {
+        let mut decoder = hpack::Decoder::default();
+        let mut call = Call::default();
+        let mut block = Vec::new();
+        let mut end_stream = false;
+        loop {
+            let (kind, flags, stream_id, payload) = self.next()?;
+            match kind {
+                SETTINGS if flags & ACK == 0 => self
+                    .io
+                    .write_all(&frame(SETTINGS, ACK, 0, &[]))
+                    .map_err(PingError::Send)?,
+                PING if flags & ACK == 0 => self
+                    .io
+                    .write_all(&frame(PING, ACK, 0, &payload))
+                    .map_err(PingError::Send)?,
+                GOAWAY => {
+                    let code = payload.get(4..8).map_or(0, |code| {
+                        u32::from_be_bytes([code[0], code[1], code[2], code[3]])
+                    });
+                    return Err(PingError::protocol(
+                        "grpc",
+                        format!("sent GOAWAY {}", h2_error(code)),
+                    ));
+                }
+                RST_STREAM if stream_id == STREAM_ID => {
+                    let code = payload.get(..4).map_or(0, |code| {
+                        u32::from_be_bytes([code[0], code[1], code[2], code[3]])
+                    });
+                    return Err(PingError::protocol(
+                        "grpc",
+                        format!("reset the call with {}", h2_error(code)),
+                    ));
+                }
+                HEADERS | CONTINUATION if stream_id == STREAM_ID => {
+                    let fragment = match kind {
+                        HEADERS => {
+                            end_stream = flags & END_STREAM != 0;
+                            let skip = match flags & PRIORITY {
+                                0 => 0,
+                                _ => 5,
+                            };
+                            unpad(flags, &payload)
+                                .and_then(|fragment| fragment.get(skip..))
+                                .ok_or_else(|| {
+                                    PingError::protocol("grpc", "sent a malformed HEADERS frame")
+                                })?
+                        }
+                        _ => &payload,
+                    };
+                    block.extend_from_slice(fragment);
+                    if flags & END_HEADERS != 0 {
+                        let headers = decoder.decode(&block).map_err(|e| {
+                            PingError::protocol("grpc", format!("sent invalid headers: {e}"))
+                        })?;
+                        block.clear();
+                        for (name, value) in headers {
+                            match name.as_str() {
+                                ":status" => call.status = Some(value),
+                                "grpc-status" => call.grpc_status = Some(value),
+                                "grpc-message" => call.grpc_message = Some(value),
+                                _ => {}
+                            }
+                        }
+                        if end_stream {
+                            return Ok(call);
+                        }
+                    }
+                }
+                DATA if stream_id == STREAM_ID => {
+                    let data = unpad(flags, &payload).ok_or_else(|| {
+                        PingError::protocol("grpc", "sent a malformed DATA frame")
+                    })?;
+                    call.message.extend_from_slice(data);
+                    if flags & END_STREAM != 0 {
+                        return Ok(call);
+                    }
+                    if !payload.is_empty() {
+                        let increment = (payload.len() as u32).to_be_bytes();
+                        let mut update = frame(WINDOW_UPDATE, 0, 0, &increment);
+                        update.extend(frame(WINDOW_UPDATE, 0, STREAM_ID, &increment));
+                        self.io.write_all(&update).map_err(PingError::Send)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn next(&mut self) -> Result<(u8, u8, u32, Vec<u8>), PingError> {
+        loop {
+            if let Some(header) = self.buf.first_chunk::<9>() {
+                let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+                if len > MAX_FRAME_LEN {
+                    return Err(PingError::protocol(
+                        "grpc",
+                        format!("sent a frame of {len} bytes"),
+                    ));
+                }
+                if self.buf.len() >= 9 + len {
+                    let (kind, flags) = (header[3], header[4]);
+                    let stream_id =
+                        u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+                            & 0x7fff_ffff;
+                    let payload = self.buf[9..9 + len].to_vec();
+                    self.buf.drain(..9 + len);
+                    return Ok((kind, flags, stream_id, payload));
+                }
+            }
+            set_timeout(self.socket, self.timeout, self.start)?;
+            let mut buf = [0; 4096];
+            match self.io.read(&mut buf) {
+                Ok(0) => return Err(PingError::protocol("grpc", "closed the connection")),
+                Ok(n) => self.buf.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(PingError::NoResponse(e)),
+            }
+        }
+    }
+}
+
+fn set_timeout(socket: &TcpStream, timeout: Duration, start: Instant) -> Result<(), PingError> {
+    let remaining = timeout.saturating_sub(start.elapsed());
+    if remaining.is_zero() {
+        return Err(PingError::NoResponse(io::ErrorKind::TimedOut.into()));
+    }
+    socket
+        .set_read_timeout(Some(remaining))
+        .map_err(PingError::NoResponse)
+}
+
+fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+    frame.extend_from_slice(&[kind, flags]);
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn unpad(flags: u8, payload: &[u8]) -> Option<&[u8]> {
+    if flags & PADDED == 0 {
+        return Some(payload);
+    }
+    let (&pad_len, rest) = payload.split_first()?;
+    rest.get(..rest.len().checked_sub(pad_len as usize)?)
+}
+
+fn message(service: &str) -> Vec<u8> {
+    let mut request = Vec::new();
+    if !service.is_empty() {
+        // field 1, length-delimited
+        request.push(0x0a);
+        encode_varint(&mut request, service.len() as u64);
+        request.extend_from_slice(service.as_bytes());
+    }
+    // not compressed, then the length of the message
+    let mut message = vec![0];
+    message.extend_from_slice(&(request.len() as u32).to_be_bytes());
+    message.extend(request);
+    message
+}
+
+fn health_status(message: &[u8]) -> Result<u64, String> {
+    let Some((prefix, mut response)) = message.split_first_chunk::<5>() else {
+        return Err("sent no response".to_owned());
+    };
+    if prefix[0] != 0 {
+        return Err("sent a compressed response".to_owned());
+    }
+    let malformed = || "sent a malformed response".to_owned();
+    // a missing field has the default value, UNKNOWN
+    let mut status = 0;
+    while !response.is_empty() {
+        let key = decode_varint(&mut response).ok_or_else(malformed)?;
+        match (key >> 3, key & 0x7) {
+            (1, 0) => status = decode_varint(&mut response).ok_or_else(malformed)?,
+            (_, 0) => _ = decode_varint(&mut response).ok_or_else(malformed)?,
+            (_, 1) => response = response.get(8..).ok_or_else(malformed)?,
+            (_, 2) => {
+                let len = decode_varint(&mut response).ok_or_else(malformed)? as usize;
+                response = response.get(len..).ok_or_else(malformed)?;
+            }
+            (_, 5) => response = response.get(4..).ok_or_else(malformed)?,
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(status)
+}
+
+fn encode_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn decode_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn h2_error(code: u32) -> String {
+    H2_ERRORS
+        .get(code as usize)
+        .map_or_else(|| format!("error {code}"), |name| (*name).to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        time::Duration,
+    };
+
+    use crate::pinger::{
+        grpc::{
+            frame, health_status, message, Tls, DATA, END_HEADERS, END_STREAM, HEADERS, PREFACE,
+            SETTINGS, WINDOW_UPDATE,
+        },
+        hpack, ping_fake_server, Grpc, Ping, PingError, Session,
+    };
+
+    fn read_frame(stream: &mut TcpStream) -> (u8, u8, Vec<u8>) {
+        let mut header = [0; 9];
+        stream.read_exact(&mut header).unwrap();
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).unwrap();
+        (header[3], header[4], payload)
+    }
+
+    fn ping(status: u8, grpc_status: &str) -> Ping {
+        let headers = hpack::encode(&[(":status", "200"), ("content-type", "application/grpc")]);
+        let trailers = hpack::encode(&[("grpc-status", grpc_status)]);
+        ping_headers(status, &headers, &trailers).0
+    }
+
+    // Pings a fake server answering the call with the `headers` block, the
+    // response and the `trailers` block in another HEADERS frame, returning
+    // the outcome and what the client sent after the request.
+    fn ping_headers(status: u8, headers: &[u8], trailers: &[u8]) -> (Ping, Vec<u8>) {
+        let grpc = Grpc {
+            timeout: Duration::from_millis(500),
+            authority: "orders.internal:50051".to_owned(),
+            service: "orders".to_owned(),
+            tls: None,
+        };
+        ping_fake_server(&Session::default(), Some(&grpc), |mut stream| {
+            let mut preface = [0; PREFACE.len()];
+            stream.read_exact(&mut preface).unwrap();
+            assert_eq!(preface, PREFACE);
+            assert_eq!(read_frame(&mut stream).0, SETTINGS);
+            let (kind, _, request) = read_frame(&mut stream);
+            assert_eq!(kind, HEADERS);
+            let request = hpack::Decoder::default().decode(&request).unwrap();
+            assert!(request.contains(&(
+                ":path".to_owned(),
+                "/grpc.health.v1.Health/Check".to_owned()
+            )));
+            let (kind, flags, request) = read_frame(&mut stream);
+            assert_eq!((kind, flags), (DATA, END_STREAM));
+            assert_eq!(request, message("orders"));
+            let mut response = frame(SETTINGS, 0, 0, &[]);
+            response.extend(frame(HEADERS, END_HEADERS, 1, headers));
+            response.extend(frame(DATA, 0, 1, &[0, 0, 0, 0, 2, 0x08, status]));
+            response.extend(frame(HEADERS, END_HEADERS | END_STREAM, 1, trailers));
+            stream.write_all(&response).unwrap();
+            // the client acknowledges the settings, then leaves
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+            rest
+        })
+    }
+
+    #[test]
+    fn test_serving() {
+        let ping = ping(1, "0");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"SERVING");
+    }
+
+    #[test]
+    fn test_not_serving() {
+        match ping(2, "0").err {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "grpc");
+                assert_eq!(reason, "status NOT_SERVING");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_grpc_status() {
+        match ping(1, "12").err {
+            Some(PingError::Protocol { reason, .. }) => {
+                assert_eq!(reason, "grpc-status 12 UNIMPLEMENTED")
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_indexed_trailers() {
+        let mut headers = vec![0x88];
+        headers.extend(hpack::encode_indexed(&[(
+            "content-type",
+            "application/grpc",
+        )]));
+        let trailers =
+            hpack::encode_indexed(&[("grpc-status", "14"), ("grpc-message", "backend down")]);
+        let (ping, sent) = ping_headers(1, &headers, &trailers);
+        match ping.err {
+            Some(PingError::Protocol { reason, .. }) => {
+                assert_eq!(reason, "grpc-status 14 UNAVAILABLE: backend down")
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+        // the DATA received is given back to the connection and the stream
+        let update = [
+            frame(WINDOW_UPDATE, 0, 0, &[0, 0, 0, 7]),
+            frame(WINDOW_UPDATE, 0, 1, &[0, 0, 0, 7]),
+        ]
+        .concat();
+        assert!(sent.windows(update.len()).any(|frames| frames == update));
+    }
+
+    #[test]
+    fn test_health_status() {
+        assert_eq!(health_status(&[0, 0, 0, 0, 0]), Ok(0));
+        // an unknown field before the status
+        assert_eq!(
+            health_status(&[0, 0, 0, 0, 5, 0x12, 0x01, b'x', 0x08, 0x02]),
+            Ok(2)
+        );
+        assert!(health_status(&[1, 0, 0, 0, 2, 0x08, 0x01]).is_err());
+        assert!(health_status(&[0, 0, 0, 0, 1, 0x08]).is_err());
+    }
+
+    #[test]
+    fn test_tls_server_name() {
+        assert!(Tls::new("orders.internal", true).is_ok());
+        assert!(Tls::new("10.0.0.1", false).is_ok());
+        assert!(Tls::new("not a name", true).is_err());
+    }
+}
@@ -0,0 +1,640 @@
+use std::{collections::VecDeque, sync::OnceLock};
+
+const DEFAULT_TABLE_SIZE: usize = 4096;
+const ENTRY_OVERHEAD: usize = 32;
+const LEAF: u16 = 0x8000;
+
+// Encodes `headers` as literals without indexing, so that the server does
+// not have to track them.
+pub fn encode(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        block.push(0);
+        encode_string(&mut block, name.as_bytes());
+        encode_string(&mut block, value.as_bytes());
+    }
+    block
+}
+
+fn encode_string(block: &mut Vec<u8>, string: &[u8]) {
+    encode_int(block, string.len(), 7, 0);
+    block.extend_from_slice(string);
+}
+
+// Encodes `headers` as Huffman coded literals with incremental indexing, as
+// servers send them.
+#[cfg(test)]
+pub fn encode_indexed(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        block.push(0x40);
+        encode_huffman(&mut block, name.as_bytes());
+        encode_huffman(&mut block, value.as_bytes());
+    }
+    block
+}
+
+#[cfg(test)]
+fn encode_huffman(block: &mut Vec<u8>, string: &[u8]) {
+    let mut data = Vec::new();
+    let (mut bits, mut len) = (0u64, 0);
+    for &byte in string {
+        let (code, code_len) = HUFFMAN_CODES[byte as usize];
+        bits = (bits << code_len) | code as u64;
+        len += code_len;
+        while len >= 8 {
+            len -= 8;
+            data.push((bits >> len) as u8);
+        }
+        bits &= (1 << len) - 1;
+    }
+    // padded with the most significant bits of EOS, all ones
+    if len > 0 {
+        data.push(((bits << (8 - len)) | ((1 << (8 - len)) - 1)) as u8);
+    }
+    encode_int(block, data.len(), 7, 0x80);
+    block.extend(data);
+}
+
+fn encode_int(block: &mut Vec<u8>, value: usize, prefix: u32, flags: u8) {
+    let max = (1 << prefix) - 1;
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max as u8);
+    let mut value = value - max;
+    while value >= 0x80 {
+        block.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+pub struct Decoder {
+    dynamic: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            dynamic: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+}
+
+impl Decoder {
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>, String> {
+        let mut headers = Vec::new();
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                // indexed field
+                let index = decode_int(&mut block, 7)?;
+                headers.push(self.entry(index)?);
+            } else if first & 0xe0 == 0x20 {
+                let size = decode_int(&mut block, 5)?;
+                if size > DEFAULT_TABLE_SIZE {
+                    return Err(format!("table size {size} larger than allowed"));
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // literal field, added to the table with incremental indexing
+                let (prefix, indexed) = match first & 0x40 != 0 {
+                    true => (6, true),
+                    false => (4, false),
+                };
+                let name = match decode_int(&mut block, prefix)? {
+                    0 => decode_string(&mut block)?,
+                    index => self.entry(index)?.0,
+                };
+                let value = decode_string(&mut block)?;
+                if indexed {
+                    self.insert(name.clone(), value.clone());
+                }
+                headers.push((name, value));
+            }
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), String> {
+        match index {
+            0 => Err("index 0".to_owned()),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_owned(), value.to_owned()))
+            }
+            _ => self
+                .dynamic
+                .get(index - 62)
+                .cloned()
+                .ok_or_else(|| format!("index {index} out of the table")),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // an entry larger than the table empties it
+        if size <= self.max_size {
+            self.size += size;
+            self.dynamic.push_front((name, value));
+        }
+    }
+
+    fn evict(&mut self, needed: usize) {
+        while self.size + needed > self.max_size {
+            let Some((name, value)) = self.dynamic.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+fn decode_int(block: &mut &[u8], prefix: u32) -> Result<usize, String> {
+    let truncated = || "truncated integer".to_owned();
+    let (&first, rest) = block.split_first().ok_or_else(truncated)?;
+    *block = rest;
+    let max = (1 << prefix) - 1;
+    let mut value = (first as usize) & max;
+    if value < max {
+        return Ok(value);
+    }
+    for shift in (0..28).step_by(7) {
+        let (&byte, rest) = block.split_first().ok_or_else(truncated)?;
+        *block = rest;
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("integer too large".to_owned())
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<String, String> {
+    let huffman = block.first().is_some_and(|first| first & 0x80 != 0);
+    let len = decode_int(block, 7)?;
+    let string = block
+        .get(..len)
+        .ok_or_else(|| "truncated string".to_owned())?;
+    *block = &block[len..];
+    let string = match huffman {
+        true => decode_huffman(string)?,
+        false => string.to_vec(),
+    };
+    Ok(String::from_utf8_lossy(&string).into_owned())
+}
+
+fn decode_huffman(data: &[u8]) -> Result<Vec<u8>, String> {
+    let tree = huffman_tree();
+    let mut decoded = Vec::new();
+    let (mut node, mut len, mut ones) = (0, 0, true);
+    for byte in data {
+        for bit in (0..8).rev() {
+            let bit = (byte >> bit) & 1;
+            ones &= bit == 1;
+            len += 1;
+            match tree[node][bit as usize] {
+                0 => return Err("invalid Huffman code".to_owned()),
+                child if child & LEAF != 0 => {
+                    decoded.push(child as u8);
+                    (node, len, ones) = (0, 0, true);
+                }
+                child => node = child as usize,
+            }
+        }
+    }
+    // the padding is made of the most significant bits of EOS, all ones
+    if len >= 8 || !ones {
+        return Err("invalid Huffman padding".to_owned());
+    }
+    Ok(decoded)
+}
+
+// The Huffman codes as a binary tree, walked a bit at a time. Every node holds
+// its two children: the index of another node, or a symbol flagged with LEAF.
+// 0 is a missing child, the root being no one's child.
+fn huffman_tree() -> &'static [[u16; 2]] {
+    static TREE: OnceLock<Vec<[u16; 2]>> = OnceLock::new();
+    TREE.get_or_init(|| {
+        let mut tree = vec![[0; 2]];
+        for (symbol, &(code, len)) in HUFFMAN_CODES.iter().enumerate() {
+            let mut node = 0;
+            for bit in (1..len).rev() {
+                let child = ((code >> bit) & 1) as usize;
+                if tree[node][child] == 0 {
+                    tree[node][child] = tree.len() as u16;
+                    tree.push([0; 2]);
+                }
+                node = tree[node][child] as usize;
+            }
+            tree[node][(code & 1) as usize] = LEAF | symbol as u16;
+        }
+        tree
+    })
+}
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+const HUFFMAN_CODES: [(u32, u8); 256] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::pinger::hpack::{decode_huffman, encode, encode_indexed, encode_int, Decoder};
+
+    #[test]
+    fn test_encode_int() {
+        // the examples of RFC 7541, C.1
+        let mut block = Vec::new();
+        encode_int(&mut block, 10, 5, 0);
+        encode_int(&mut block, 1337, 5, 0);
+        encode_int(&mut block, 42, 8, 0);
+        assert_eq!(block, [10, 31, 154, 10, 42]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let block = encode(&[("content-type", "application/grpc"), ("te", "trailers")]);
+        let headers = Decoder::default().decode(&block).unwrap();
+        assert_eq!(
+            headers,
+            [
+                ("content-type".to_owned(), "application/grpc".to_owned()),
+                ("te".to_owned(), "trailers".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_huffman() {
+        // www.example.com, from RFC 7541, C.4.1
+        let data = [
+            0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff,
+        ];
+        assert_eq!(decode_huffman(&data).unwrap(), b"www.example.com");
+        assert!(decode_huffman(&[0xf1, 0x00]).is_err());
+        // EOS, which a string must not hold
+        assert!(decode_huffman(&[0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_huffman_round_trip() {
+        let printable: String = (' '..='~').collect();
+        let headers = vec![("grpc-message".to_owned(), printable.clone())];
+        let mut decoder = Decoder::default();
+        let block = encode_indexed(&[("grpc-message", &printable)]);
+        assert_eq!(decoder.decode(&block).unwrap(), headers);
+        // the literal was added to the dynamic table
+        assert_eq!(decoder.decode(&[0xbe]).unwrap(), headers);
+    }
+
+    // The responses of RFC 7541, C.6, sharing a dynamic table of 256 bytes.
+    #[test]
+    fn test_dynamic_table() {
+        let mut decoder = Decoder {
+            max_size: 256,
+            ..Default::default()
+        };
+        let first = [
+            0x48, 0x82, 0x64, 0x02, 0x58, 0x85, 0xae, 0xc3, 0x77, 0x1a, 0x4b, 0x61, 0x96, 0xd0,
+            0x7a, 0xbe, 0x94, 0x10, 0x54, 0xd4, 0x44, 0xa8, 0x20, 0x05, 0x95, 0x04, 0x0b, 0x81,
+            0x66, 0xe0, 0x82, 0xa6, 0x2d, 0x1b, 0xff, 0x6e, 0x91, 0x9d, 0x29, 0xad, 0x17, 0x18,
+            0x63, 0xc7, 0x8f, 0x0b, 0x97, 0xc8, 0xe9, 0xae, 0x82, 0xae, 0x43, 0xd3,
+        ];
+        let headers = decoder.decode(&first).unwrap();
+        assert_eq!(headers[0], (":status".to_owned(), "302".to_owned()));
+        assert_eq!(headers[3].1, "https://www.example.com");
+        assert_eq!(decoder.size, 222);
+        let second = [0x48, 0x83, 0x64, 0x0e, 0xff, 0xc1, 0xc0, 0xbf];
+        let headers = decoder.decode(&second).unwrap();
+        assert_eq!(headers[0], (":status".to_owned(), "307".to_owned()));
+        assert_eq!(headers[1].0, "cache-control");
+        assert_eq!(headers[3].1, "https://www.example.com");
+        assert_eq!(decoder.size, 222);
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    io::Write,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::{read_until, sanitize, Handshake, PingError, Response};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const DISCONNECT: [u8; 2] = [0xe0, 0];
+const CLEAN_SESSION: u8 = 0x02;
+const PASSWORD: u8 = 0x40;
+const USERNAME: u8 = 0x80;
+
+// The keep alive announced, longer than the probe lasts.
+const KEEP_ALIVE_SECS: u16 = 60;
+const MAX_PACKET_LEN: usize = 4096;
+const RETURN_CODES: &[&str] = &[
+    "accepted",
+    "unacceptable protocol version",
+    "identifier rejected",
+    "server unavailable",
+    "bad username or password",
+    "not authorized",
+];
+
+const REASON_CODES: &[(u8, &str)] = &[
+    (0x00, "success"),
+    (0x80, "unspecified error"),
+    (0x81, "malformed packet"),
+    (0x82, "protocol error"),
+    (0x83, "implementation specific error"),
+    (0x84, "unsupported protocol version"),
+    (0x85, "client identifier not valid"),
+    (0x86, "bad user name or password"),
+    (0x87, "not authorized"),
+    (0x88, "server unavailable"),
+    (0x89, "server busy"),
+    (0x8a, "banned"),
+    (0x8c, "bad authentication method"),
+    (0x90, "topic name invalid"),
+    (0x95, "packet too large"),
+    (0x97, "quota exceeded"),
+    (0x99, "payload format invalid"),
+    (0x9a, "retain not supported"),
+    (0x9b, "QoS not supported"),
+    (0x9c, "use another server"),
+    (0x9d, "server moved"),
+    (0x9f, "connection rate exceeded"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MqttVersion {
+    V311,
+    V5,
+}
+
+pub struct Mqtt {
+    pub timeout: Duration,
+    pub version: MqttVersion,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Handshake for Mqtt {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let start = Instant::now();
+        stream.write_all(&self.connect()).map_err(PingError::Send)?;
+        let packet = read_until(stream, self.timeout, MAX_PACKET_LEN, |data| {
+            packet_len(data).is_some_and(|len| data.len() >= len)
+        })?;
+        let time = start.elapsed();
+        let Some(body) = packet_len(&packet.data).and_then(|len| {
+            let header_len = len - remaining_len(&packet.data)?.0;
+            packet.data.get(header_len..len)
+        }) else {
+            return Err(PingError::protocol(
+                "mqtt",
+                format!("truncated packet \"{}\"", sanitize(&packet.data)),
+            ));
+        };
+        if packet.data[0] != CONNACK {
+            return Err(PingError::protocol(
+                "mqtt",
+                format!(
+                    "answered with packet type {} instead of CONNACK",
+                    packet.data[0] >> 4
+                ),
+            ));
+        }
+        let Some(&code) = body.get(1) else {
+            return Err(PingError::protocol(
+                "mqtt",
+                "sent a CONNACK without return code",
+            ));
+        };
+        let name = match self.version {
+            MqttVersion::V311 => RETURN_CODES.get(code as usize).copied(),
+            MqttVersion::V5 => REASON_CODES
+                .iter()
+                .find(|(value, _)| *value == code)
+                .map(|(_, name)| *name),
+        }
+        .unwrap_or("unknown");
+        if code != 0 {
+            return Err(PingError::protocol(
+                "mqtt",
+                format!("refused the connection: {name} ({code})"),
+            ));
+        }
+        _ = stream.write_all(&DISCONNECT);
+        Ok(Response {
+            data: format!("CONNACK {name}").into_bytes(),
+            time,
+        })
+    }
+}
+
+impl Mqtt {
+    fn connect(&self) -> Vec<u8> {
+        let mut flags = CLEAN_SESSION;
+        let mut body = Vec::new();
+        encode_string(&mut body, "MQTT");
+        body.push(match self.version {
+            MqttVersion::V311 => 4,
+            MqttVersion::V5 => 5,
+        });
+        if self.username.is_some() {
+            flags |= USERNAME;
+        }
+        if self.password.is_some() {
+            flags |= PASSWORD;
+        }
+        body.push(flags);
+        body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+        if self.version == MqttVersion::V5 {
+            // no properties
+            body.push(0);
+        }
+        encode_string(&mut body, &self.client_id);
+        for field in [&self.username, &self.password].into_iter().flatten() {
+            encode_string(&mut body, field);
+        }
+        let mut packet = vec![CONNECT];
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            if len == 0 {
+                packet.push(byte);
+                break;
+            }
+            packet.push(byte | 0x80);
+        }
+        packet.extend(body);
+        packet
+    }
+}
+
+fn encode_string(data: &mut Vec<u8>, string: &str) {
+    data.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    data.extend_from_slice(string.as_bytes());
+}
+
+fn remaining_len(data: &[u8]) -> Option<(usize, usize)> {
+    let mut len = 0;
+    for (i, byte) in data.iter().skip(1).take(4).enumerate() {
+        len += ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((len, i + 1));
+        }
+    }
+    None
+}
+
+fn packet_len(data: &[u8]) -> Option<usize> {
+    let (len, len_bytes) = remaining_len(data)?;
+    Some(1 + len_bytes + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use crate::pinger::{
+        mqtt::{packet_len, MqttVersion},
+        ping_fake_server, Mqtt, Ping, PingError, Session,
+    };
+
+    fn mqtt(version: MqttVersion) -> Mqtt {
+        Mqtt {
+            timeout: Duration::from_millis(500),
+            version,
+            client_id: "probe".to_owned(),
+            username: Some("sensor".to_owned()),
+            password: Some("secret".to_owned()),
+        }
+    }
+
+    fn ping(mqtt: Mqtt, connack: &'static [u8]) -> (Ping, Vec<u8>) {
+        ping_fake_server(&Session::default(), Some(&mqtt), |mut stream| {
+            let mut received = Vec::new();
+            let mut buf = [0; 256];
+            while packet_len(&received).is_none_or(|len| received.len() < len) {
+                let n = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(connack).unwrap();
+            // the DISCONNECT, if any
+            stream.read_to_end(&mut received).unwrap();
+            received
+        })
+    }
+
+    #[test]
+    fn test_connect() {
+        let mut expected = b"\x10\x21\0\x04MQTT\x04\xc2\0\x3c".to_vec();
+        expected.extend_from_slice(b"\0\x05probe\0\x06sensor\0\x06secret");
+        assert_eq!(mqtt(MqttVersion::V311).connect(), expected);
+        let connect = Mqtt {
+            username: None,
+            password: None,
+            ..mqtt(MqttVersion::V5)
+        }
+        .connect();
+        assert_eq!(connect, b"\x10\x12\0\x04MQTT\x05\x02\0\x3c\0\0\x05probe");
+    }
+
+    #[test]
+    fn test_accepted() {
+        let (ping, received) = ping(mqtt(MqttVersion::V311), b"\x20\x02\0\0");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"CONNACK accepted");
+        assert!(received.ends_with(b"\xe0\0"));
+    }
+
+    #[test]
+    fn test_accepted_v5() {
+        // a property with the maximum QoS after the reason code
+        let (ping, _) = ping(mqtt(MqttVersion::V5), b"\x20\x05\0\0\x02\x24\x01");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"CONNACK success");
+    }
+
+    #[test]
+    fn test_bad_credentials() {
+        match ping(mqtt(MqttVersion::V311), b"\x20\x02\0\x04").0.err {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "mqtt");
+                assert_eq!(
+                    reason,
+                    "refused the connection: bad username or password (4)"
+                );
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_server_busy_v5() {
+        match ping(mqtt(MqttVersion::V5), b"\x20\x03\0\x89\0").0.err {
+            Some(PingError::Protocol { reason, .. }) => {
+                assert_eq!(reason, "refused the connection: server busy (137)")
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_not_connack() {
+        let (ping, _) = ping(mqtt(MqttVersion::V311), b"\xd0\0");
+        assert!(matches!(ping.err, Some(PingError::Protocol { .. })));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::{read_until, sanitize, Handshake, PingError, Response};
+
+const PROTOCOL_VERSION: u8 = 10;
+const ERR_PACKET: u8 = 0xff;
+const MAX_PACKET_LEN: usize = 4096;
+
+pub struct Mysql {
+    pub timeout: Duration,
+}
+
+impl Handshake for Mysql {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let start = Instant::now();
+        let packet = read_until(stream, self.timeout, MAX_PACKET_LEN, |data| {
+            packet_len(data).is_some_and(|len| data.len() >= len)
+        })?;
+        let Some(payload) = packet_len(&packet.data).and_then(|len| packet.data.get(4..len)) else {
+            return Err(PingError::protocol(
+                "mysql",
+                format!("truncated packet \"{}\"", sanitize(&packet.data)),
+            ));
+        };
+        match payload.split_first() {
+            Some((&PROTOCOL_VERSION, rest)) => {
+                let version = rest.split(|b| *b == 0).next().unwrap_or_default();
+                Ok(Response {
+                    data: version.to_vec(),
+                    time: start.elapsed(),
+                })
+            }
+            Some((&ERR_PACKET, rest)) => Err(PingError::protocol("mysql", error_packet(rest))),
+            Some((other, _)) => Err(PingError::protocol(
+                "mysql",
+                format!("unsupported protocol version {other}"),
+            )),
+            None => Err(PingError::protocol("mysql", "empty greeting")),
+        }
+    }
+
+    fn server(&self, response: &Response) -> Option<String> {
+        Some(sanitize(&response.data))
+    }
+}
+
+// The length of the packet at the start of `data`, including its header
+// made of the payload length on 3 bytes and a sequence number.
+fn packet_len(data: &[u8]) -> Option<usize> {
+    let len = data.get(..3)?;
+    Some(4 + u32::from_le_bytes([len[0], len[1], len[2], 0]) as usize)
+}
+
+fn error_packet(rest: &[u8]) -> String {
+    let Some((code, message)) = rest.split_first_chunk::<2>() else {
+        return "truncated error packet".to_owned();
+    };
+    let message = match message.strip_prefix(b"#") {
+        Some(message) => message.get(5..).unwrap_or_default(),
+        None => message,
+    };
+    format!("error {} {}", u16::from_le_bytes(*code), sanitize(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::pinger::{ping_greeting, Mysql, PingError};
+
+    const MYSQL: Mysql = Mysql {
+        timeout: Duration::from_millis(500),
+    };
+
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(0);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn test_version() {
+        let mut payload = b"\x0a8.0.34\0".to_vec();
+        // connection id, then the first part of the auth plugin data
+        payload.extend_from_slice(&[7, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 0]);
+        let (ping, _) = ping_greeting(&MYSQL, &packet(&payload));
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"8.0.34");
+        assert_eq!(ping.server.unwrap(), "8.0.34");
+    }
+
+    #[test]
+    fn test_too_many_connections() {
+        match ping_greeting(&MYSQL, &packet(b"\xff\x10\x04#08004Too many connections"))
+            .0
+            .err
+        {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "mysql");
+                assert_eq!(reason, "error 1040 Too many connections");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_host_blocked() {
+        // pre-4.1 error packets have no SQL state
+        match ping_greeting(&MYSQL, &packet(b"\xff\x69\x04Host is blocked"))
+            .0
+            .err
+        {
+            Some(PingError::Protocol { reason, .. }) => {
+                assert_eq!(reason, "error 1129 Host is blocked")
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut greeting = packet(b"\x0a8.0.34\0");
+        greeting.pop();
+        assert!(matches!(
+            ping_greeting(&MYSQL, &greeting).0.err,
+            Some(PingError::Protocol { .. })
+        ));
+    }
+}
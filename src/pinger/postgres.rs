@@ -0,0 +1,168 @@
+use std::{
+    io::Write,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::{read_until, sanitize, Handshake, PingError, Response};
+
+const PROTOCOL_VERSION: u32 = 0x0003_0000;
+
+// The user the probe logs in as. The login is not meant to succeed.
+const USER: &str = "rustcping";
+const MAX_MESSAGE_LEN: usize = 4096;
+
+pub struct Postgres {
+    pub timeout: Duration,
+}
+
+impl Handshake for Postgres {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let start = Instant::now();
+        let data = self.startup(stream)?;
+        Ok(Response {
+            data,
+            time: start.elapsed(),
+        })
+    }
+}
+
+impl Postgres {
+    fn startup(&self, stream: &mut TcpStream) -> Result<Vec<u8>, PingError> {
+        let mut parameters = Vec::new();
+        for value in ["user", USER, ""] {
+            parameters.extend_from_slice(value.as_bytes());
+            parameters.push(0);
+        }
+        let mut message = ((8 + parameters.len()) as u32).to_be_bytes().to_vec();
+        message.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        message.extend(parameters);
+        stream.write_all(&message).map_err(PingError::Send)?;
+        let answer = read_until(stream, self.timeout, MAX_MESSAGE_LEN, |data| {
+            message_len(data).is_some_and(|len| data.len() >= len)
+        })?;
+        let Some(body) = message_len(&answer.data).and_then(|len| answer.data.get(5..len)) else {
+            return Err(PingError::protocol(
+                "postgres",
+                format!("truncated message \"{}\"", sanitize(&answer.data)),
+            ));
+        };
+        match answer.data[0] {
+            b'R' => Ok(b"authentication requested".to_vec()),
+            b'v' => Ok(b"protocol version negotiation".to_vec()),
+            b'E' => {
+                let (code, message) = error_fields(body);
+                // a server refusing the probe user is up; one shutting down,
+                // starting up or out of connections is not
+                match code.starts_with("57") || code.starts_with("53") {
+                    true => Err(PingError::protocol("postgres", format!("{code} {message}"))),
+                    false => Ok(format!("{code} {message}").into_bytes()),
+                }
+            }
+            other => Err(PingError::protocol(
+                "postgres",
+                format!("answered \"{}\" to the startup message", sanitize(&[other])),
+            )),
+        }
+    }
+}
+
+fn message_len(data: &[u8]) -> Option<usize> {
+    let len: [u8; 4] = data.get(1..5)?.try_into().ok()?;
+    Some(1 + u32::from_be_bytes(len) as usize)
+}
+
+fn error_fields(body: &[u8]) -> (String, String) {
+    let (mut code, mut message) = (String::new(), String::new());
+    for field in body.split(|b| *b == 0) {
+        match field.split_first() {
+            Some((b'C', value)) => code = sanitize(value),
+            Some((b'M', value)) => message = sanitize(value),
+            _ => {}
+        }
+    }
+    (code, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::pinger::{ping_answer, Ping, PingError, Postgres};
+
+    const POSTGRES: Postgres = Postgres {
+        timeout: Duration::from_millis(500),
+    };
+
+    fn error_response(code: &str, message: &str) -> Vec<u8> {
+        let body = format!("SFATAL\0C{code}\0M{message}\0\0");
+        let mut response = vec![b'E'];
+        response.extend_from_slice(&(4 + body.len() as u32).to_be_bytes());
+        response.extend_from_slice(body.as_bytes());
+        response
+    }
+
+    fn ping(answer: Vec<u8>) -> Ping {
+        ping_answer(&POSTGRES, &answer).0
+    }
+
+    #[test]
+    fn test_tls_only() {
+        let ping = ping(error_response(
+            "28000",
+            "no pg_hba.conf entry for host \"10.0.0.7\", user \"rustcping\", no encryption",
+        ));
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+    }
+
+    #[test]
+    fn test_too_many_connections() {
+        match ping(error_response("53300", "sorry, too many clients already")).err {
+            Some(PingError::Protocol { reason, .. }) => {
+                assert_eq!(reason, "53300 sorry, too many clients already")
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_authentication_requested() {
+        // AuthenticationSASL with SCRAM-SHA-256
+        let mut request = b"R\0\0\0\x17\0\0\0\x0a".to_vec();
+        request.extend_from_slice(b"SCRAM-SHA-256\0\0");
+        let (ping, startup) = ping_answer(&POSTGRES, &request);
+        assert_eq!(startup, b"\0\0\0\x18\0\x03\0\0user\0rustcping\0\0");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"authentication requested");
+    }
+
+    #[test]
+    fn test_unknown_role() {
+        let ping = ping(error_response("28000", "role \"rustcping\" does not exist"));
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+    }
+
+    #[test]
+    fn test_starting_up() {
+        match ping(error_response(
+            "57P03",
+            "the database system is starting up",
+        ))
+        .err
+        {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "postgres");
+                assert_eq!(reason, "57P03 the database system is starting up");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_not_postgres() {
+        assert!(matches!(
+            ping(b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec()).err,
+            Some(PingError::Protocol { .. })
+        ));
+    }
+}
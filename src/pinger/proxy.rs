@@ -0,0 +1,370 @@
+use std::{
+    fmt,
+    io::Write,
+    net::{IpAddr, TcpStream},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use super::{read_until, sanitize, PingError};
+use crate::target::split_host_port;
+
+const MAX_HTTP_HEADER_LEN: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyKind {
+    Socks5,
+    Http,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+}
+
+impl FromStr for Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, authority) = s
+            .split_once("://")
+            .ok_or_else(|| "expected a proxy like socks5://host:port".to_owned())?;
+        let (kind, default_port) = match scheme.to_ascii_lowercase().as_str() {
+            "socks5" => (ProxyKind::Socks5, 1080),
+            "http" => (ProxyKind::Http, 8080),
+            scheme => return Err(format!("'{scheme}' is not one of socks5, http")),
+        };
+        let (host, port) = split_host_port(authority.trim_end_matches('/'))?;
+        if host.is_empty() {
+            return Err("missing proxy host".to_owned());
+        }
+        Ok(Proxy {
+            kind,
+            host: host.to_owned(),
+            port: port.unwrap_or(default_port),
+        })
+    }
+}
+
+impl fmt::Display for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.kind {
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::Http => "http",
+        };
+        write!(f, "{scheme}://{}", host_port(&self.host, self.port))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tunnel {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    pub timeout: Duration,
+}
+
+impl Tunnel {
+    pub(super) fn open(&self, stream: &mut TcpStream) -> Result<(), PingError> {
+        let deadline = Instant::now() + self.timeout;
+        match self.kind {
+            ProxyKind::Socks5 => self.socks5(stream, deadline),
+            ProxyKind::Http => self.http(stream, deadline),
+        }
+    }
+
+    fn socks5(&self, stream: &mut TcpStream, deadline: Instant) -> Result<(), PingError> {
+        // version 5, one authentication method: none
+        send(stream, &[5, 1, 0])?;
+        match read_exact(stream, 2, deadline)?[..] {
+            [5, 0] => {}
+            [5, 0xff] => return Err(PingError::proxy("SOCKS5 proxy requires authentication")),
+            _ => return Err(PingError::proxy("not a SOCKS5 proxy")),
+        }
+        // version 5, CONNECT, reserved, then the address of the target
+        let mut request = vec![5, 1, 0];
+        match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(1);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let name = self.host.as_bytes();
+                let len = u8::try_from(name.len())
+                    .map_err(|_| PingError::proxy("target name too long for SOCKS5"))?;
+                request.push(3);
+                request.push(len);
+                request.extend_from_slice(name);
+            }
+        }
+        request.extend_from_slice(&self.port.to_be_bytes());
+        send(stream, &request)?;
+        // the reply ends with the address bound by the proxy, whose length
+        // depends on its type
+        let head = read_exact(stream, 5, deadline)?;
+        if head[0] != 5 {
+            return Err(PingError::proxy("not a SOCKS5 proxy"));
+        }
+        if head[1] != 0 {
+            return Err(PingError::proxy(format!(
+                "SOCKS5 reply {} ({})",
+                head[1],
+                socks5_reply(head[1])
+            )));
+        }
+        let rest = match head[3] {
+            1 => 4 - 1 + 2,
+            4 => 16 - 1 + 2,
+            3 => head[4] as usize + 2,
+            _ => {
+                return Err(PingError::proxy(
+                    "SOCKS5 proxy sent an unknown address type",
+                ))
+            }
+        };
+        read_exact(stream, rest, deadline)?;
+        Ok(())
+    }
+
+    fn http(&self, stream: &mut TcpStream, deadline: Instant) -> Result<(), PingError> {
+        let target = host_port(&self.host, self.port);
+        send(
+            stream,
+            format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes(),
+        )?;
+        // one byte at a time, not to read what the target sends through the
+        // tunnel right after the header
+        let mut header = Vec::new();
+        while !header.ends_with(b"\r\n\r\n") {
+            if header.len() == MAX_HTTP_HEADER_LEN {
+                return Err(PingError::proxy("HTTP proxy sent a too long header"));
+            }
+            header.extend(read_exact(stream, 1, deadline)?);
+        }
+        let status_line = header.split(|b| *b == b'\n').next().unwrap_or_default();
+        let status_line = sanitize(status_line);
+        let mut fields = status_line.splitn(3, ' ');
+        let (version, code, reason) = (fields.next(), fields.next(), fields.next());
+        if !version.is_some_and(|version| version.starts_with("HTTP/")) {
+            return Err(PingError::proxy("not an HTTP proxy"));
+        }
+        match code {
+            Some(code) if code.starts_with('2') => Ok(()),
+            Some(code) => Err(PingError::proxy(format!(
+                "HTTP {code} {}",
+                reason.unwrap_or_default()
+            ))),
+            None => Err(PingError::proxy("HTTP proxy sent no status code")),
+        }
+    }
+}
+
+fn send(stream: &mut TcpStream, data: &[u8]) -> Result<(), PingError> {
+    stream
+        .write_all(data)
+        .map_err(|e| PingError::proxy(format!("cannot talk to the proxy: {e}")))
+}
+
+fn read_exact(stream: &mut TcpStream, len: usize, deadline: Instant) -> Result<Vec<u8>, PingError> {
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let missing = len - data.len();
+        let chunk = read_until(stream, timeout, missing, |chunk| chunk.len() == missing)
+            .map_err(|e| PingError::proxy(format!("proxy {e}")))?;
+        data.extend(chunk.data);
+    }
+    Ok(data)
+}
+
+fn socks5_reply(reply: u8) -> &'static str {
+    match reply {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn host_port(host: &str, port: u16) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{host}]:{port}"),
+        _ => format!("{host}:{port}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    use crate::pinger::{
+        ping_fake_server,
+        proxy::{Proxy, ProxyKind, Tunnel},
+        Bind, Ping, PingError, PingWithoutTimeout, Pinger, ResponseCheck, Session,
+    };
+
+    fn ping(kind: ProxyKind, host: &str, serve: fn(TcpStream)) -> Ping {
+        let session = Session {
+            tunnel: Some(Tunnel {
+                kind,
+                host: host.to_owned(),
+                port: 22,
+                timeout: Duration::from_millis(500),
+            }),
+            banner: Some(ResponseCheck {
+                max_len: 64,
+                timeout: Duration::from_millis(500),
+                expect: None,
+            }),
+            ..Default::default()
+        };
+        ping_fake_server(&session, None, serve).0
+    }
+
+    fn read(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_socks5() {
+        let ping = ping(ProxyKind::Socks5, "bastion.internal", |mut stream| {
+            assert_eq!(read(&mut stream, 3), [5, 1, 0]);
+            stream.write_all(&[5, 0]).unwrap();
+            let mut request = read(&mut stream, 5);
+            assert_eq!(request, [5, 1, 0, 3, 16]);
+            request = read(&mut stream, 18);
+            assert_eq!(&request[..16], b"bastion.internal");
+            assert_eq!(&request[16..], [0, 22]);
+            // the banner comes along with the reply
+            stream
+                .write_all(b"\x05\x00\x00\x01\x0a\x00\x00\x01\x9c\x40SSH-2.0-OpenSSH_9.6\r\n")
+                .unwrap();
+        });
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert!(ping.proxy_time.is_some());
+        assert!(ping.connect_time >= ping.proxy_time);
+        assert_eq!(ping.banner.unwrap().data, b"SSH-2.0-OpenSSH_9.6\r\n");
+    }
+
+    #[test]
+    fn test_socks5_refused() {
+        let ping = ping(ProxyKind::Socks5, "10.0.0.2", |mut stream| {
+            read(&mut stream, 3);
+            stream.write_all(&[5, 0]).unwrap();
+            assert_eq!(read(&mut stream, 10), [5, 1, 0, 1, 10, 0, 0, 2, 0, 22]);
+            stream.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+        match ping.err {
+            Some(PingError::Proxy(reason)) => {
+                assert_eq!(reason, "SOCKS5 reply 5 (connection refused)")
+            }
+            other => panic!("expected a proxy error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_socks5_authentication() {
+        let ping = ping(ProxyKind::Socks5, "::1", |mut stream| {
+            read(&mut stream, 3);
+            stream.write_all(&[5, 0xff]).unwrap();
+        });
+        assert!(matches!(ping.err, Some(PingError::Proxy(_))));
+    }
+
+    #[test]
+    fn test_http_connect() {
+        let ping = ping(ProxyKind::Http, "2001:db8::1", |mut stream| {
+            let expected = b"CONNECT [2001:db8::1]:22 HTTP/1.1\r\nHost: [2001:db8::1]:22\r\n\r\n";
+            assert_eq!(read(&mut stream, expected.len()), expected);
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nSSH-2.0-dropbear\r\n")
+                .unwrap();
+        });
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.banner.unwrap().data, b"SSH-2.0-dropbear\r\n");
+    }
+
+    #[test]
+    fn test_http_proxy_authentication() {
+        let ping = ping(ProxyKind::Http, "db.internal", |mut stream| {
+            _ = stream.read(&mut [0; 128]);
+            stream
+                .write_all(
+                    b"HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n",
+                )
+                .unwrap();
+        });
+        match ping.err {
+            Some(PingError::Proxy(reason)) => {
+                assert_eq!(reason, "HTTP 407 Proxy Authentication Required")
+            }
+            other => panic!("expected a proxy error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_proxy_unreachable() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let ping = PingWithoutTimeout {
+            socket: ([127, 0, 0, 1], port).into(),
+            bind: Bind::default(),
+            session: Session {
+                tunnel: Some(Tunnel {
+                    kind: ProxyKind::Http,
+                    host: "db.internal".to_owned(),
+                    port: 5432,
+                    timeout: Duration::from_millis(500),
+                }),
+                ..Default::default()
+            },
+        }
+        .ping();
+        match ping.err {
+            Some(PingError::Proxy(reason)) => {
+                assert!(
+                    reason.starts_with("cannot connect to the proxy"),
+                    "{reason}"
+                )
+            }
+            other => panic!("expected a proxy error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_proxy() {
+        assert_eq!(
+            "socks5://bastion".parse::<Proxy>(),
+            Ok(Proxy {
+                kind: ProxyKind::Socks5,
+                host: "bastion".to_owned(),
+                port: 1080,
+            })
+        );
+        let proxy: Proxy = "HTTP://[::1]:3128/".parse().unwrap();
+        assert_eq!((proxy.kind, proxy.port), (ProxyKind::Http, 3128));
+        assert_eq!(proxy.to_string(), "http://[::1]:3128");
+        assert!("bastion:1080".parse::<Proxy>().is_err());
+        assert!("socks4://bastion".parse::<Proxy>().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    io::Write,
+    net::{IpAddr, SocketAddr, TcpStream},
+};
+
+use super::PingError;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_PROXY: u8 = 0x21;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    V1,
+    V2,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub version: ProxyProtocol,
+    pub source: Option<IpAddr>,
+    pub source_port: Option<u16>,
+}
+
+impl ProxyHeader {
+    pub(super) fn send(&self, stream: &mut TcpStream) -> Result<(), PingError> {
+        let local = stream.local_addr().map_err(PingError::Send)?;
+        let peer = stream.peer_addr().map_err(PingError::Send)?;
+        let source = SocketAddr::new(
+            self.source.unwrap_or(local.ip()),
+            self.source_port.unwrap_or(local.port()),
+        );
+        stream
+            .write_all(&self.encode(source, peer))
+            .map_err(PingError::Send)
+    }
+
+    fn encode(&self, source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+        // both addresses should be of the same family
+        let (source_ip, destination_ip) = match (source.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V6(destination)) => {
+                (IpAddr::V6(source.to_ipv6_mapped()), IpAddr::V6(destination))
+            }
+            (IpAddr::V6(source), IpAddr::V4(destination)) => {
+                (IpAddr::V6(source), IpAddr::V6(destination.to_ipv6_mapped()))
+            }
+            addresses => addresses,
+        };
+        let (source_port, destination_port) = (source.port(), destination.port());
+        match self.version {
+            ProxyProtocol::V1 => {
+                let family = match source_ip {
+                    IpAddr::V4(_) => "TCP4",
+                    IpAddr::V6(_) => "TCP6",
+                };
+                format!("PROXY {family} {source_ip} {destination_ip} {source_port} {destination_port}\r\n")
+                    .into_bytes()
+            }
+            ProxyProtocol::V2 => {
+                let (family, mut addresses) = match (source_ip, destination_ip) {
+                    (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                        (0x11, [source.octets(), destination.octets()].concat())
+                    }
+                    (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                        (0x21, [source.octets(), destination.octets()].concat())
+                    }
+                    _ => unreachable!("the addresses were made of the same family"),
+                };
+                addresses.extend_from_slice(&source_port.to_be_bytes());
+                addresses.extend_from_slice(&destination_port.to_be_bytes());
+                let mut header = V2_SIGNATURE.to_vec();
+                header.extend_from_slice(&[V2_PROXY, family]);
+                header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+                header.extend(addresses);
+                header
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    use crate::pinger::{
+        proxy_protocol::{ProxyHeader, ProxyProtocol},
+        Bind, PingWithoutTimeout, Pinger, ResponseCheck, Session,
+    };
+
+    fn header(version: ProxyProtocol) -> ProxyHeader {
+        ProxyHeader {
+            version,
+            source: None,
+            source_port: None,
+        }
+    }
+
+    #[test]
+    fn test_v1() {
+        let header = header(ProxyProtocol::V1).encode(
+            "192.0.2.1:40000".parse().unwrap(),
+            "10.0.0.5:443".parse().unwrap(),
+        );
+        assert_eq!(header, b"PROXY TCP4 192.0.2.1 10.0.0.5 40000 443\r\n");
+    }
+
+    #[test]
+    fn test_v1_mixed_families() {
+        let header = header(ProxyProtocol::V1).encode(
+            "192.0.2.1:40000".parse().unwrap(),
+            "[2001:db8::5]:443".parse().unwrap(),
+        );
+        assert_eq!(
+            header,
+            b"PROXY TCP6 ::ffff:192.0.2.1 2001:db8::5 40000 443\r\n"
+        );
+    }
+
+    #[test]
+    fn test_v2() {
+        let header = header(ProxyProtocol::V2).encode(
+            "192.0.2.1:40000".parse().unwrap(),
+            "10.0.0.5:443".parse().unwrap(),
+        );
+        let mut expected = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\0\x0c".to_vec();
+        expected.extend_from_slice(&[192, 0, 2, 1, 10, 0, 0, 5, 0x9c, 0x40, 0x01, 0xbb]);
+        assert_eq!(header, expected);
+    }
+
+    #[test]
+    fn test_v2_ipv6() {
+        let header = header(ProxyProtocol::V2).encode(
+            "[2001:db8::1]:40000".parse().unwrap(),
+            "[2001:db8::5]:443".parse().unwrap(),
+        );
+        assert_eq!(&header[12..16], [0x21, 0x21, 0, 36]);
+        assert_eq!(header.len(), 16 + 36);
+    }
+
+    // The header comes before the request, with the spoofed source and the
+    // real destination.
+    #[test]
+    fn test_header_before_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn({
+            let listener = listener.try_clone().unwrap();
+            move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let expected = format!("PROXY TCP4 203.0.113.7 127.0.0.1 1234 {port}\r\nPING\r\n");
+                let mut received = vec![0; expected.len()];
+                stream.read_exact(&mut received).unwrap();
+                assert_eq!(String::from_utf8(received).unwrap(), expected);
+                stream.write_all(b"+PONG\r\n").unwrap();
+            }
+        });
+        let ping = PingWithoutTimeout {
+            socket: listener.local_addr().unwrap(),
+            bind: Bind::default(),
+            session: Session {
+                proxy_header: Some(ProxyHeader {
+                    version: ProxyProtocol::V1,
+                    source: Some("203.0.113.7".parse().unwrap()),
+                    source_port: Some(1234),
+                }),
+                request: Some(b"PING\r\n".to_vec()),
+                response: Some(ResponseCheck {
+                    max_len: 64,
+                    timeout: Duration::from_millis(500),
+                    expect: None,
+                }),
+                ..Default::default()
+            },
+        }
+        .ping();
+        server.join().unwrap();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"+PONG\r\n");
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    io::Write,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::{read_until, sanitize, Handshake, PingError, Response};
+
+const PING: &[u8] = b"*1\r\n$4\r\nPING\r\n";
+const MAX_REPLY_LEN: usize = 512;
+
+pub struct Redis {
+    pub timeout: Duration,
+}
+
+impl Handshake for Redis {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let start = Instant::now();
+        stream.write_all(PING).map_err(PingError::Send)?;
+        let reply = read_until(stream, self.timeout, MAX_REPLY_LEN, |data| {
+            data.ends_with(b"\r\n")
+        })?;
+        let time = start.elapsed();
+        let Some(line) = reply.data.strip_suffix(b"\r\n") else {
+            return Err(PingError::protocol(
+                "redis",
+                format!("truncated reply \"{}\"", sanitize(&reply.data)),
+            ));
+        };
+        // a server requiring authentication is up, even if the probe
+        // cannot log in
+        if line == b"+PONG" || line.starts_with(b"-NOAUTH") {
+            return Ok(Response {
+                data: line.to_vec(),
+                time,
+            });
+        }
+        Err(PingError::protocol(
+            "redis",
+            format!("replied \"{}\"", sanitize(line)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::pinger::{ping_answer, PingError, Redis};
+
+    const REDIS: Redis = Redis {
+        timeout: Duration::from_millis(500),
+    };
+
+    #[test]
+    fn test_pong() {
+        let (ping, request) = ping_answer(&REDIS, b"+PONG\r\n");
+        assert_eq!(request, b"*1\r\n$4\r\nPING\r\n");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"+PONG");
+    }
+
+    #[test]
+    fn test_noauth() {
+        assert!(ping_answer(&REDIS, b"-NOAUTH Authentication required.\r\n")
+            .0
+            .err
+            .is_none());
+    }
+
+    #[test]
+    fn test_loading() {
+        match ping_answer(
+            &REDIS,
+            b"-LOADING Redis is loading the dataset in memory\r\n",
+        )
+        .0
+        .err
+        {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "redis");
+                assert!(reason.contains("-LOADING"), "{reason}");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(matches!(
+            ping_answer(&REDIS, b"+PO").0.err,
+            Some(PingError::Protocol { .. })
+        ));
+    }
+}
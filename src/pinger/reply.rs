@@ -0,0 +1,84 @@
+use std::{net::TcpStream, time::Duration};
+
+use super::{read_until, sanitize, PingError, Response};
+
+const MAX_REPLY_LEN: usize = 4096;
+
+pub struct Reply {
+    pub code: u16,
+    pub text: String,
+    pub response: Response,
+}
+
+pub fn read_reply(
+    stream: &mut TcpStream,
+    timeout: Duration,
+    protocol: &'static str,
+) -> Result<Reply, PingError> {
+    let response = read_until(stream, timeout, MAX_REPLY_LEN, is_complete)?;
+    if !is_complete(&response.data) {
+        return Err(PingError::protocol(
+            protocol,
+            format!("truncated reply \"{}\"", sanitize(&response.data)),
+        ));
+    }
+    let first_line = response
+        .data
+        .split(|b| *b == b'\n')
+        .next()
+        .unwrap_or_default();
+    let code = first_line
+        .get(..3)
+        .and_then(|code| std::str::from_utf8(code).ok())
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| {
+            PingError::protocol(protocol, format!("replied \"{}\"", sanitize(first_line)))
+        })?;
+    Ok(Reply {
+        code,
+        text: text(&response.data),
+        response,
+    })
+}
+
+pub fn text(data: &[u8]) -> String {
+    let first_line = data.split(|b| *b == b'\n').next().unwrap_or_default();
+    sanitize(first_line.get(4..).unwrap_or_default())
+}
+
+// Whether `data` ends with the last line of a reply, the lines in between
+// being free to start with anything, as RFC 959 and 5321 allow.
+fn is_complete(data: &[u8]) -> bool {
+    let Some(data) = data.strip_suffix(b"\n") else {
+        return false;
+    };
+    let Some(code) = data.get(..3) else {
+        return false;
+    };
+    let last_line = data.rsplit(|b| *b == b'\n').next().unwrap_or_default();
+    last_line
+        .strip_prefix(code)
+        .is_some_and(|rest| rest.starts_with(b" ") || rest.trim_ascii_end().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pinger::reply::is_complete;
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete(b"220 ftp.example.com\r\n"));
+        assert!(is_complete(
+            b"220-Welcome\r\n220-to\r\n220 ftp.example.com\r\n"
+        ));
+        assert!(is_complete(b"250\r\n"));
+        assert!(!is_complete(b"220-Welcome\r\n"));
+        // a line in between looking like the last one of another code
+        assert!(!is_complete(b"220-Welcome\r\nHi, welcome\r\n"));
+        assert!(!is_complete(b"220-Welcome\r\n230 logged in\r\n"));
+        assert!(is_complete(
+            b"220-Welcome\r\nHi, welcome\r\n220 ftp.example.com\r\n"
+        ));
+        assert!(!is_complete(b"220 ftp.exam"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    io::Write,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::{
+    reply::{self, read_reply},
+    Handshake, PingError, Response,
+};
+
+pub struct Smtp {
+    pub timeout: Duration,
+    pub ehlo: Option<String>,
+}
+
+impl Handshake for Smtp {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let start = Instant::now();
+        let greeting = self.command(stream, None, 220)?;
+        if let Some(name) = &self.ehlo {
+            self.command(stream, Some(&format!("EHLO {name}")), 250)?;
+            self.command(stream, Some("QUIT"), 221)?;
+        }
+        Ok(Response {
+            data: greeting.data,
+            time: start.elapsed(),
+        })
+    }
+
+    fn server(&self, response: &Response) -> Option<String> {
+        Some(reply::text(&response.data))
+    }
+}
+
+impl Smtp {
+    fn command(
+        &self,
+        stream: &mut TcpStream,
+        command: Option<&str>,
+        code: u16,
+    ) -> Result<Response, PingError> {
+        if let Some(command) = command {
+            stream
+                .write_all(format!("{command}\r\n").as_bytes())
+                .map_err(PingError::Send)?;
+        }
+        let reply = read_reply(stream, self.timeout, "smtp")?;
+        if reply.code != code {
+            let after = command.map_or("".to_owned(), |command| {
+                let verb = command.split(' ').next().unwrap_or_default();
+                format!(" to {verb}")
+            });
+            return Err(PingError::protocol(
+                "smtp",
+                format!("replied {} {}{after}", reply.code, reply.text),
+            ));
+        }
+        Ok(reply.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        time::Duration,
+    };
+
+    use crate::pinger::{ping_fake_server, Ping, PingError, Session, Smtp};
+
+    fn ping(greeting: &'static str, ehlo: Option<&str>, ehlo_reply: &'static str) -> Ping {
+        let smtp = Smtp {
+            timeout: Duration::from_millis(500),
+            ehlo: ehlo.map(str::to_owned),
+        };
+        ping_fake_server(&Session::default(), Some(&smtp), |mut stream| {
+            stream.write_all(greeting.as_bytes()).unwrap();
+            let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+            while let Some(Ok(line)) = lines.next() {
+                match line.split(' ').next() {
+                    Some("EHLO") => stream.write_all(ehlo_reply.as_bytes()).unwrap(),
+                    Some("QUIT") => stream.write_all(b"221 Bye\r\n").unwrap(),
+                    _ => stream.write_all(b"500 What?\r\n").unwrap(),
+                }
+            }
+        })
+        .0
+    }
+
+    #[test]
+    fn test_greeting() {
+        let ping = ping("220 mx.example.com ESMTP Postfix\r\n", None, "");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.server.unwrap(), "mx.example.com ESMTP Postfix");
+    }
+
+    #[test]
+    fn test_ehlo() {
+        let ping = ping(
+            "220-mx.example.com ESMTP\r\n220 no UCE\r\n",
+            Some("probe.example.com"),
+            "250-mx.example.com\r\n250-PIPELINING\r\n250 8BITMIME\r\n",
+        );
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+    }
+
+    #[test]
+    fn test_ehlo_refused() {
+        match ping(
+            "220 mx.example.com ESMTP\r\n",
+            Some("probe.example.com"),
+            "550 go away\r\n",
+        )
+        .err
+        {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "smtp");
+                assert_eq!(reason, "replied 550 go away to EHLO");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_busy() {
+        match ping("421 Too many connections\r\n", None, "").err {
+            Some(PingError::Protocol { reason, .. }) => {
+                assert_eq!(reason, "replied 421 Too many connections")
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::{read_until, sanitize, Handshake, PingError, Response};
+
+const VERSIONS: &[&[u8]] = &[b"SSH-2.0-", b"SSH-1.99-"];
+
+// The most bytes read before the identification, as servers may send other
+// lines first.
+const MAX_LEN: usize = 4096;
+
+pub struct Ssh {
+    pub timeout: Duration,
+}
+
+impl Handshake for Ssh {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let start = Instant::now();
+        let response = read_until(stream, self.timeout, MAX_LEN, |data| {
+            identification(data).is_some()
+        })?;
+        let time = start.elapsed();
+        let Some(line) = identification(&response.data) else {
+            return Err(PingError::protocol(
+                "ssh",
+                format!(
+                    "sent no identification but \"{}\"",
+                    sanitize(&response.data)
+                ),
+            ));
+        };
+        if !VERSIONS.iter().any(|version| line.starts_with(version)) {
+            return Err(PingError::protocol(
+                "ssh",
+                format!("unsupported identification \"{}\"", sanitize(line)),
+            ));
+        }
+        Ok(Response {
+            data: line.to_vec(),
+            time,
+        })
+    }
+
+    fn server(&self, response: &Response) -> Option<String> {
+        let software = response.data.splitn(3, |b| *b == b'-').nth(2)?;
+        let version = software.split(|b| *b == b' ').next()?;
+        Some(sanitize(version))
+    }
+}
+
+fn identification(data: &[u8]) -> Option<&[u8]> {
+    let complete = &data[..data.iter().rposition(|b| *b == b'\n')?];
+    complete
+        .split(|b| *b == b'\n')
+        .find(|line| line.starts_with(b"SSH-"))
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::pinger::{ping_greeting, PingError, Ssh};
+
+    const SSH: Ssh = Ssh {
+        timeout: Duration::from_millis(500),
+    };
+
+    #[test]
+    fn test_identification() {
+        let (ping, _) = ping_greeting(&SSH, b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13\r\n");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(
+            ping.response.unwrap().data,
+            b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13"
+        );
+        assert_eq!(ping.server.unwrap(), "OpenSSH_9.6p1");
+    }
+
+    #[test]
+    fn test_lines_before_identification() {
+        let (ping, _) = ping_greeting(&SSH, b"Authorized use only\r\nSSH-1.99-Cisco-1.25\r\n");
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.server.unwrap(), "Cisco-1.25");
+    }
+
+    #[test]
+    fn test_ssh1() {
+        match ping_greeting(&SSH, b"SSH-1.5-OldServer\r\n").0.err {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "ssh");
+                assert_eq!(reason, "unsupported identification \"SSH-1.5-OldServer\"");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_no_identification() {
+        let (ping, _) = ping_greeting(&SSH, b"220 mx.example.com ESMTP\r\n");
+        assert!(matches!(ping.err, Some(PingError::Protocol { .. })));
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use super::{bind_device, Bind, Ping, Pinger, SourcePorts};
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const ACK: u8 = 0x10;
+
+pub struct PingSyn {
+    target: SocketAddr,
+    source: IpAddr,
+    timeout: Duration,
+    bind: Bind,
+    raw: UdpSocket,
+}
+
+impl PingSyn {
+    // Opens the raw socket, which requires CAP_NET_RAW.
+    pub fn new(target: SocketAddr, timeout: Duration, bind: Bind) -> io::Result<PingSyn> {
+        let raw = Socket::new(Domain::for_address(target), Type::RAW, Some(Protocol::TCP))?;
+        if let Some(interface) = &bind.interface {
+            bind_device(&raw, interface)?;
+        }
+        let source = match bind.source {
+            Some(source) => source,
+            None => route_source(&target)?,
+        };
+        Ok(PingSyn {
+            target,
+            source,
+            timeout,
+            bind,
+            raw: raw.into(),
+        })
+    }
+
+    fn reserve_port(&self) -> io::Result<Socket> {
+        let source_port = self.bind.source_ports.as_ref().map(SourcePorts::next);
+        let socket = Socket::new(
+            Domain::for_address(self.target),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        socket.set_reuse_address(source_port.is_some())?;
+        socket.bind(&SocketAddr::new(self.source, source_port.unwrap_or(0)).into())?;
+        Ok(socket)
+    }
+
+    fn handshake(&self, source: SocketAddr) -> io::Result<Duration> {
+        let seq = initial_sequence();
+        let syn = segment(source, self.target, seq, SYN);
+        let destination = SocketAddr::new(self.target.ip(), 0);
+        let start = Instant::now();
+        self.raw.send_to(&syn, destination)?;
+        let mut buf = [0; 1500];
+        loop {
+            let remaining = self.timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.raw.set_read_timeout(Some(remaining))?;
+            let (n, from) = match self.raw.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(io::ErrorKind::TimedOut.into())
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let Some(reply) = Reply::parse(&buf[..n], from.ip()) else {
+                continue;
+            };
+            if reply.source != self.target
+                || reply.destination_port != source.port()
+                || reply.ack != seq.wrapping_add(1)
+            {
+                continue;
+            }
+            let elapsed = start.elapsed();
+            if reply.flags & RST != 0 {
+                return Err(io::ErrorKind::ConnectionRefused.into());
+            }
+            if reply.flags & (SYN | ACK) == SYN | ACK {
+                let rst = segment(source, self.target, reply.ack, RST);
+                self.raw.send_to(&rst, destination)?;
+                return Ok(elapsed);
+            }
+        }
+    }
+}
+
+impl Pinger for PingSyn {
+    fn ping(&self) -> Ping {
+        let reservation = match self.reserve_port() {
+            Ok(reservation) => reservation,
+            Err(e) => {
+                return Ping {
+                    err: Some(e.into()),
+                    ..Default::default()
+                }
+            }
+        };
+        let source = reservation
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_socket())
+            .unwrap_or(SocketAddr::new(self.source, 0));
+        let start = Instant::now();
+        let result = self.handshake(source);
+        Ping {
+            connect_time: Some(*result.as_ref().unwrap_or(&start.elapsed())),
+            err: result.err().map(Into::into),
+            source_port: Some(source.port()),
+            ..Default::default()
+        }
+    }
+}
+
+fn route_source(target: &SocketAddr) -> io::Result<IpAddr> {
+    let unspecified = match target {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let udp = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    udp.connect(target)?;
+    Ok(udp.local_addr()?.ip())
+}
+
+fn initial_sequence() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
+
+// Builds a TCP segment without payload. SYNs carry the MSS option, as some
+// stacks drop the ones without it.
+fn segment(source: SocketAddr, destination: SocketAddr, seq: u32, flags: u8) -> Vec<u8> {
+    let with_mss = flags & SYN != 0;
+    let header_len: u8 = if with_mss { 24 } else { 20 };
+    let window: u16 = if flags & RST != 0 { 0 } else { 64240 };
+    let mut segment = Vec::with_capacity(header_len as usize);
+    segment.extend(source.port().to_be_bytes());
+    segment.extend(destination.port().to_be_bytes());
+    segment.extend(seq.to_be_bytes());
+    segment.extend(0u32.to_be_bytes());
+    segment.push((header_len / 4) << 4);
+    segment.push(flags);
+    segment.extend(window.to_be_bytes());
+    segment.extend(0u16.to_be_bytes());
+    segment.extend(0u16.to_be_bytes());
+    if with_mss {
+        segment.extend([2, 4]);
+        segment.extend(1460u16.to_be_bytes());
+    }
+    let checksum = checksum(source.ip(), destination.ip(), &segment);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+fn checksum(source: IpAddr, destination: IpAddr, segment: &[u8]) -> u16 {
+    fn add(sum: u32, bytes: &[u8]) -> u32 {
+        bytes.chunks(2).fold(sum, |sum, chunk| {
+            sum + u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32
+        })
+    }
+    let mut sum = match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let sum = add(0, &source.octets());
+            let sum = add(sum, &destination.octets());
+            let sum = add(sum, &[0, 6]);
+            add(sum, &(segment.len() as u16).to_be_bytes())
+        }
+        (source, destination) => {
+            let sum = add(0, &to_ipv6(source).octets());
+            let sum = add(sum, &to_ipv6(destination).octets());
+            let sum = add(sum, &(segment.len() as u32).to_be_bytes());
+            add(sum, &[0, 0, 0, 6])
+        }
+    };
+    sum = add(sum, segment);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+struct Reply {
+    source: SocketAddr,
+    destination_port: u16,
+    ack: u32,
+    flags: u8,
+}
+
+impl Reply {
+    // Parses a packet read from the raw socket: IPv4 raw sockets return the
+    // IP header too, IPv6 ones only the TCP segment.
+    fn parse(packet: &[u8], from: IpAddr) -> Option<Reply> {
+        let segment = match from {
+            IpAddr::V4(_) => {
+                let header_len = (*packet.first()? & 0x0f) as usize * 4;
+                packet.get(header_len..)?
+            }
+            IpAddr::V6(_) => packet,
+        };
+        if segment.len() < 20 {
+            return None;
+        }
+        let port = |i: usize| u16::from_be_bytes([segment[i], segment[i + 1]]);
+        Some(Reply {
+            source: SocketAddr::new(from, port(0)),
+            destination_port: port(2),
+            ack: u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]),
+            flags: segment[13] & (FIN | SYN | RST | ACK),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::{SocketAddr, TcpListener},
+        time::Duration,
+    };
+
+    use crate::pinger::{
+        syn::{checksum, segment, Reply, ACK, RST, SYN},
+        Bind, PingError, Pinger,
+    };
+
+    use super::PingSyn;
+
+    // Raw sockets need CAP_NET_RAW: without it the tests that send packets are skipped.
+    fn pinger(target: SocketAddr) -> Option<PingSyn> {
+        match PingSyn::new(target, Duration::from_secs(1), Bind::default()) {
+            Ok(pinger) => Some(pinger),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => None,
+            Err(e) => panic!("cannot open the raw socket: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        let source: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let destination: SocketAddr = "10.0.0.2:443".parse().unwrap();
+        let syn = segment(source, destination, 1234, SYN);
+        assert_eq!(syn.len(), 24);
+        assert_eq!(checksum(source.ip(), destination.ip(), &syn), 0);
+        let source: SocketAddr = "[2001:db8::1]:40000".parse().unwrap();
+        let destination: SocketAddr = "[2001:db8::2]:443".parse().unwrap();
+        let rst = segment(source, destination, 1235, RST);
+        assert_eq!(rst.len(), 20);
+        assert_eq!(checksum(source.ip(), destination.ip(), &rst), 0);
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let server: SocketAddr = "[2001:db8::2]:443".parse().unwrap();
+        let client: SocketAddr = "[2001:db8::1]:40000".parse().unwrap();
+        let mut syn_ack = segment(server, client, 99, SYN | ACK);
+        syn_ack[8..12].copy_from_slice(&1235u32.to_be_bytes());
+        let reply = Reply::parse(&syn_ack, server.ip()).unwrap();
+        assert_eq!(reply.source, server);
+        assert_eq!(reply.destination_port, 40000);
+        assert_eq!(reply.ack, 1235);
+        assert_eq!(reply.flags, SYN | ACK);
+        assert!(Reply::parse(&syn_ack[..12], server.ip()).is_none());
+    }
+
+    #[test]
+    fn test_syn_open_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let Some(pinger) = pinger(listener.local_addr().unwrap()) else {
+            return;
+        };
+        let ping = pinger.ping();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert!(ping.connect_time.unwrap() < Duration::from_secs(1));
+        // the handshake is never completed, so nothing can be accepted
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn test_syn_closed_port() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let Some(pinger) = pinger(SocketAddr::new("127.0.0.1".parse().unwrap(), port)) else {
+            return;
+        };
+        match pinger.ping().err {
+            Some(PingError::Connect(e)) => assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused),
+            other => panic!("expected a refused connection, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::{io, net::TcpStream, time::Duration};
+
+// The bytes of `struct tcp_info` up to `tcpi_total_retrans`, which every
+// kernel since 2.6 fills.
+const TCP_INFO_LEN: usize = 104;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpInfo {
+    pub rtt: Duration,
+    pub rtt_var: Duration,
+    pub retransmits: u32,
+    pub mss: u32,
+}
+
+impl TcpInfo {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn read(stream: &TcpStream) -> io::Result<TcpInfo> {
+        use std::os::fd::AsRawFd;
+
+        let mut buf = [0u8; TCP_INFO_LEN];
+        let mut len = TCP_INFO_LEN as libc::socklen_t;
+        // SAFETY: the option value points to a buffer of the given length
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_INFO,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        parse(&buf[..len as usize])
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn read(_: &TcpStream) -> io::Result<TcpInfo> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "TCP_INFO is only supported on Linux",
+        ))
+    }
+}
+
+// Parses the fields of `struct tcp_info`, which are in the byte order of
+// the host.
+fn parse(data: &[u8]) -> io::Result<TcpInfo> {
+    if data.len() < TCP_INFO_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("TCP_INFO of {} bytes is too short", data.len()),
+        ));
+    }
+    let field =
+        |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap_or_default());
+    Ok(TcpInfo {
+        rtt: Duration::from_micros(field(68).into()),
+        rtt_var: Duration::from_micros(field(72).into()),
+        retransmits: field(100),
+        mss: field(16),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, net::TcpStream, time::Duration};
+
+    use crate::pinger::tcp_info::{parse, TcpInfo, TCP_INFO_LEN};
+
+    #[test]
+    fn test_parse() {
+        let mut data = [0u8; TCP_INFO_LEN];
+        data[16..20].copy_from_slice(&1460u32.to_ne_bytes());
+        data[68..72].copy_from_slice(&1500u32.to_ne_bytes());
+        data[72..76].copy_from_slice(&250u32.to_ne_bytes());
+        data[100..104].copy_from_slice(&2u32.to_ne_bytes());
+        assert_eq!(
+            parse(&data).unwrap(),
+            TcpInfo {
+                rtt: Duration::from_micros(1500),
+                rtt_var: Duration::from_micros(250),
+                retransmits: 2,
+                mss: 1460,
+            }
+        );
+        assert!(parse(&data[..TCP_INFO_LEN - 1]).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let info = TcpInfo::read(&stream).unwrap();
+        assert_eq!(info.retransmits, 0);
+        assert!(info.mss > 0);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeSet,
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use regex::bytes::Regex;
+
+use super::{Bind, Ping, PingError, Pinger, Response, SourcePorts};
+
+const SEQ_PLACEHOLDER: &[u8] = b"{seq}";
+const DEFAULT_DATAGRAM: &[u8] = b"rustcping seq={seq}";
+
+// How many of the last sequence numbers are remembered to tell a duplicate
+// reply from a late one.
+const SEQ_WINDOW: u32 = 1024;
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+pub struct PingUdp {
+    socket: UdpSocket,
+    // The datagram sent by every probe, `DEFAULT_DATAGRAM` if `None`. When
+    // it holds `SEQ_PLACEHOLDER`, the sequence number of the probe replaces
+    // it, and the replies echoing another one are not matched to the probe;
+    // otherwise any reply is.
+    payload: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    expect: Option<Regex>,
+    seq: Cell<u32>,
+    answered: RefCell<BTreeSet<u32>>,
+}
+
+impl PingUdp {
+    pub fn new(
+        target: SocketAddr,
+        bind: &Bind,
+        payload: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+        expect: Option<Regex>,
+    ) -> io::Result<PingUdp> {
+        let source_port = bind.source_ports.as_ref().map(SourcePorts::next);
+        let socket: UdpSocket = bind.datagram_socket(&target, source_port)?.into();
+        socket.connect(target)?;
+        Ok(PingUdp {
+            socket,
+            payload,
+            timeout,
+            expect,
+            seq: Cell::new(1),
+            answered: RefCell::default(),
+        })
+    }
+
+    fn template(&self) -> &[u8] {
+        self.payload.as_deref().unwrap_or(DEFAULT_DATAGRAM)
+    }
+
+    fn datagram(&self, seq: u32) -> Vec<u8> {
+        match split_template(self.template()) {
+            Some((prefix, suffix)) => [prefix, seq.to_string().as_bytes(), suffix].concat(),
+            None => self.template().to_vec(),
+        }
+    }
+
+    fn receive(&self, seq: u32, start: Instant, ping: &mut Ping) -> Result<Vec<u8>, PingError> {
+        let mut buf = vec![0; MAX_DATAGRAM_LEN];
+        loop {
+            let remaining = self
+                .timeout
+                .map(|timeout| timeout.saturating_sub(start.elapsed()));
+            if remaining.is_some_and(|remaining| remaining.is_zero()) {
+                return Err(PingError::NoResponse(io::ErrorKind::TimedOut.into()));
+            }
+            self.socket
+                .set_read_timeout(remaining)
+                .map_err(PingError::NoResponse)?;
+            let len = match self.socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    return Err(PingError::PortUnreachable(e))
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(PingError::NoResponse(e)),
+            };
+            let data = &buf[..len];
+            // a reply not echoing a sequence number can only answer this probe
+            let reply_seq = split_template(self.template())
+                .and_then(|(prefix, suffix)| parse_seq(data, prefix, suffix))
+                .unwrap_or(seq);
+            let mut answered = self.answered.borrow_mut();
+            if !answered.insert(reply_seq) {
+                ping.duplicate_replies += 1;
+                continue;
+            }
+            if reply_seq != seq {
+                ping.out_of_order_replies += 1;
+                continue;
+            }
+            if let Some(&oldest) = answered.first() {
+                if seq.wrapping_sub(oldest) >= SEQ_WINDOW {
+                    *answered = answered.split_off(&seq.saturating_sub(SEQ_WINDOW - 1));
+                }
+            }
+            return Ok(data.to_vec());
+        }
+    }
+}
+
+impl Pinger for PingUdp {
+    fn ping(&self) -> Ping {
+        let seq = self.seq.get();
+        self.seq.set(seq.wrapping_add(1));
+        let mut ping = Ping {
+            source_port: self.socket.local_addr().ok().map(|addr| addr.port()),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        if let Err(e) = self.socket.send(&self.datagram(seq)) {
+            ping.err = Some(match e.kind() {
+                io::ErrorKind::ConnectionRefused => PingError::PortUnreachable(e),
+                _ => PingError::Send(e),
+            });
+            return ping;
+        }
+        match self.receive(seq, start, &mut ping) {
+            Ok(data) => {
+                let time = start.elapsed();
+                ping.connect_time = Some(time);
+                if self
+                    .expect
+                    .as_ref()
+                    .is_some_and(|expect| !expect.is_match(&data))
+                {
+                    ping.err = Some(PingError::UnexpectedResponse(data));
+                } else if self.payload.is_some() {
+                    ping.response = Some(Response { data, time });
+                }
+            }
+            Err(e) => ping.err = Some(e),
+        }
+        ping
+    }
+}
+
+fn split_template(template: &[u8]) -> Option<(&[u8], &[u8])> {
+    let i = template
+        .windows(SEQ_PLACEHOLDER.len())
+        .position(|window| window == SEQ_PLACEHOLDER)?;
+    Some((&template[..i], &template[i + SEQ_PLACEHOLDER.len()..]))
+}
+
+fn parse_seq(data: &[u8], prefix: &[u8], suffix: &[u8]) -> Option<u32> {
+    let seq = data.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    std::str::from_utf8(seq).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, thread, time::Duration};
+
+    use crate::pinger::{
+        udp::{parse_seq, split_template},
+        Bind, PingError, PingUdp, Pinger,
+    };
+
+    fn pinger(server: &UdpSocket, payload: Option<Vec<u8>>) -> PingUdp {
+        PingUdp::new(
+            server.local_addr().unwrap(),
+            &Bind::default(),
+            payload,
+            Some(Duration::from_millis(300)),
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_echo() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let pinger = pinger(&server, None);
+        let echo = thread::spawn(move || {
+            let mut buf = [0; 64];
+            for _ in 0..2 {
+                let (len, from) = server.recv_from(&mut buf).unwrap();
+                server.send_to(&buf[..len], from).unwrap();
+            }
+        });
+        for _ in 0..2 {
+            let ping = pinger.ping();
+            assert!(ping.err.is_none(), "{:?}", ping.err);
+            assert!(ping.connect_time.is_some());
+            assert_eq!(ping.duplicate_replies, 0);
+        }
+        echo.join().unwrap();
+    }
+
+    #[test]
+    fn test_payload() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let pinger = pinger(&server, Some(b"status".to_vec()));
+        let echo = thread::spawn(move || {
+            let mut buf = [0; 64];
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"status");
+            server.send_to(b"ok", from).unwrap();
+        });
+        let ping = pinger.ping();
+        echo.join().unwrap();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.response.unwrap().data, b"ok");
+    }
+
+    // A late reply to the first probe is not taken for the second one's.
+    #[test]
+    fn test_payload_seq() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let pinger = pinger(&server, Some(b"status {seq}\n".to_vec()));
+        assert!(matches!(pinger.ping().err, Some(PingError::NoResponse(_))));
+        let echo = thread::spawn(move || {
+            let mut buf = [0; 64];
+            let (len, _) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"status 1\n");
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"status 2\n");
+            server.send_to(b"status 1\n", from).unwrap();
+            server
+        });
+        let ping = pinger.ping();
+        let _server = echo.join().unwrap();
+        assert!(matches!(ping.err, Some(PingError::NoResponse(_))));
+        assert_eq!(ping.out_of_order_replies, 1);
+    }
+
+    #[test]
+    fn test_port_unreachable() {
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let pinger = pinger(&closed, None);
+        drop(closed);
+        assert!(matches!(
+            pinger.ping().err,
+            Some(PingError::PortUnreachable(_))
+        ));
+    }
+
+    #[test]
+    fn test_no_reply() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let pinger = pinger(&server, None);
+        assert!(matches!(pinger.ping().err, Some(PingError::NoResponse(_))));
+    }
+
+    // The first probe is answered late, after the second one was sent, and
+    // the second one twice.
+    #[test]
+    fn test_out_of_order_and_duplicate() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let pinger = pinger(&server, None);
+        assert!(matches!(pinger.ping().err, Some(PingError::NoResponse(_))));
+        let echo = thread::spawn(move || {
+            let mut buf = [0; 64];
+            let (len, _) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"rustcping seq=1");
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"rustcping seq=2");
+            server.send_to(b"rustcping seq=1", from).unwrap();
+            server.send_to(&buf[..len], from).unwrap();
+            server.send_to(&buf[..len], from).unwrap();
+            server
+        });
+        let ping = pinger.ping();
+        let _server = echo.join().unwrap();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(ping.out_of_order_replies, 1);
+        // the duplicate comes after the reply, and is noticed by the next probe
+        let ping = pinger.ping();
+        assert!(matches!(ping.err, Some(PingError::NoResponse(_))));
+        assert_eq!(ping.duplicate_replies, 1);
+    }
+
+    #[test]
+    fn test_parse_seq() {
+        let (prefix, suffix) = split_template(b"rustcping seq={seq}").unwrap();
+        assert_eq!(parse_seq(b"rustcping seq=42", prefix, suffix), Some(42));
+        assert_eq!(parse_seq(b"rustcping seq=", prefix, suffix), None);
+        assert_eq!(parse_seq(b"PONG", prefix, suffix), None);
+        let (prefix, suffix) = split_template(b"id={seq};").unwrap();
+        assert_eq!(parse_seq(b"id=7;", prefix, suffix), Some(7));
+        assert_eq!(split_template(b"status"), None);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    io,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, SockAddr, Socket, Type};
+
+use super::{Ping, Pinger};
+
+pub struct PingUnix {
+    pub path: PathBuf,
+    pub timeout: Option<Duration>,
+}
+
+impl PingUnix {
+    fn connect(&self) -> io::Result<UnixStream> {
+        let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+        let address = SockAddr::unix(&self.path)?;
+        match self.timeout {
+            Some(timeout) => socket.connect_timeout(&address, timeout)?,
+            None => socket.connect(&address)?,
+        }
+        Ok(socket.into())
+    }
+}
+
+impl Pinger for PingUnix {
+    fn ping(&self) -> Ping {
+        let start = Instant::now();
+        let result = self.connect();
+        Ping {
+            connect_time: Some(start.elapsed()),
+            err: result.err().map(Into::into),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, os::unix::net::UnixListener, path::PathBuf, time::Duration};
+
+    use crate::pinger::{PingError, PingUnix, Pinger};
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rustcping_{name}_{}.sock", std::process::id()));
+        _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_ping_unix() {
+        let path = socket_path("listening");
+        let _listener = UnixListener::bind(&path).unwrap();
+        let pinger = PingUnix {
+            path: path.clone(),
+            timeout: Some(Duration::from_secs(1)),
+        };
+        let ping = pinger.ping();
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert!(ping.connect_time.is_some());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_ping_unix_not_listening() {
+        let path = socket_path("stale");
+        // a socket file left behind by a dead service refuses connections
+        drop(UnixListener::bind(&path).unwrap());
+        let pinger = PingUnix {
+            path: path.clone(),
+            timeout: None,
+        };
+        match pinger.ping().err {
+            Some(PingError::Connect(e)) => assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused),
+            other => panic!("expected a refused connection, got {other:?}"),
+        }
+        fs::remove_file(path).unwrap();
+        match pinger.ping().err {
+            Some(PingError::Connect(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("expected a missing socket, got {other:?}"),
+        }
+    }
+}
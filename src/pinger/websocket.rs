@@ -0,0 +1,384 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::Write,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+use super::{read_until, sanitize, Handshake, PingError, Response};
+
+// Appended to the key by the server before hashing it into the accept value.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HEADER_LEN: usize = 8192;
+const MAX_FRAMES_LEN: usize = 65536;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+const PING_PAYLOAD: &[u8] = b"rustcping";
+const CLOSE_NORMAL: u16 = 1000;
+
+pub struct Websocket {
+    pub timeout: Duration,
+    pub host: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub ping: bool,
+}
+
+impl Handshake for Websocket {
+    fn run(&self, stream: &mut TcpStream) -> Result<Response, PingError> {
+        let key = BASE64_STANDARD
+            .encode([random_u64().to_be_bytes(), random_u64().to_be_bytes()].concat());
+        let start = Instant::now();
+        stream
+            .write_all(self.request(&key).as_bytes())
+            .map_err(PingError::Send)?;
+        let reply = read_until(stream, self.timeout, MAX_HEADER_LEN, |data| {
+            header_end(data).is_some()
+        })?;
+        let time = start.elapsed();
+        let Some(end) = header_end(&reply.data) else {
+            return Err(PingError::protocol(
+                "websocket",
+                format!("truncated response \"{}\"", sanitize(&reply.data)),
+            ));
+        };
+        let (head, frames) = reply.data.split_at(end);
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines();
+        let status = lines.next().unwrap_or_default().to_owned();
+        if status.split(' ').nth(1) != Some("101") {
+            return Err(PingError::protocol(
+                "websocket",
+                format!("replied \"{}\"", sanitize(status.as_bytes())),
+            ));
+        }
+        let headers: Vec<(&str, &str)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| *value)
+        };
+        if !header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket")) {
+            return Err(PingError::protocol(
+                "websocket",
+                "switched to another protocol than websocket".to_owned(),
+            ));
+        }
+        let expected = accept_key(&key);
+        match header("Sec-WebSocket-Accept") {
+            Some(accept) if accept == expected => {}
+            Some(accept) => {
+                return Err(PingError::protocol(
+                    "websocket",
+                    format!("sent Sec-WebSocket-Accept {accept} instead of {expected}"),
+                ))
+            }
+            None => {
+                return Err(PingError::protocol(
+                    "websocket",
+                    "sent no Sec-WebSocket-Accept",
+                ))
+            }
+        }
+        if self.ping {
+            self.ping(stream, frames)?;
+        }
+        // the server would otherwise see the connection dropped abruptly
+        _ = stream.write_all(&frame(OPCODE_CLOSE, &CLOSE_NORMAL.to_be_bytes()));
+        Ok(Response {
+            data: status.into_bytes(),
+            time,
+        })
+    }
+}
+
+impl Websocket {
+    fn request(&self, key: &str) -> String {
+        let headers: String = self
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}\r\n"))
+            .collect();
+        format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n{headers}\r\n",
+            self.path, self.host
+        )
+    }
+
+    // Sends a ping frame and waits for its pong, skipping the other frames.
+    // `pending` is what the server sent after the response to the upgrade.
+    fn ping(&self, stream: &mut TcpStream, pending: &[u8]) -> Result<(), PingError> {
+        stream
+            .write_all(&frame(OPCODE_PING, PING_PAYLOAD))
+            .map_err(PingError::Send)?;
+        let answer = |data: &[u8]| {
+            frames(&[pending, data].concat())
+                .into_iter()
+                .find(|(opcode, payload)| {
+                    *opcode == OPCODE_CLOSE || (*opcode == OPCODE_PONG && payload == PING_PAYLOAD)
+                })
+        };
+        let received = match read_until(stream, self.timeout, MAX_FRAMES_LEN, |data| {
+            answer(data).is_some()
+        }) {
+            Ok(received) => received.data,
+            Err(PingError::NoResponse(e)) => {
+                return Err(PingError::protocol(
+                    "websocket",
+                    format!("sent no pong: {e}"),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        match answer(&received) {
+            Some((OPCODE_PONG, _)) => Ok(()),
+            Some((_, payload)) => Err(PingError::protocol(
+                "websocket",
+                match payload.get(..2) {
+                    Some(code) => format!(
+                        "closed with status {}",
+                        u16::from_be_bytes([code[0], code[1]])
+                    ),
+                    None => "closed the connection".to_owned(),
+                },
+            )),
+            None => Err(PingError::protocol("websocket", "sent no pong")),
+        }
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    BASE64_STANDARD.encode(sha1.finalize())
+}
+
+fn header_end(data: &[u8]) -> Option<usize> {
+    data.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| position + 4)
+}
+
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    // control frames carry at most 125 bytes
+    debug_assert!(payload.len() <= 125);
+    let mask = (random_u64() as u32).to_be_bytes();
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+    frame
+}
+
+fn frames(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut frames = Vec::new();
+    while let Some((opcode, payload, len)) = parse_frame(data) {
+        frames.push((opcode, payload));
+        data = &data[len..];
+    }
+    frames
+}
+
+fn parse_frame(data: &[u8]) -> Option<(u8, Vec<u8>, usize)> {
+    let (&first, rest) = data.split_first()?;
+    let (&second, mut rest) = rest.split_first()?;
+    let payload_len = match second & 0x7f {
+        126 => {
+            let (len, tail) = rest.split_first_chunk::<2>()?;
+            rest = tail;
+            u16::from_be_bytes(*len) as usize
+        }
+        127 => {
+            let (len, tail) = rest.split_first_chunk::<8>()?;
+            rest = tail;
+            usize::try_from(u64::from_be_bytes(*len)).ok()?
+        }
+        len => len as usize,
+    };
+    let mask = match second & 0x80 != 0 {
+        true => {
+            let (mask, tail) = rest.split_first_chunk::<4>()?;
+            rest = tail;
+            Some(*mask)
+        }
+        false => None,
+    };
+    let payload = rest.get(..payload_len)?;
+    let payload = match mask {
+        Some(mask) => payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(b, m)| b ^ m)
+            .collect(),
+        None => payload.to_vec(),
+    };
+    let len = data.len() - rest.len() + payload_len;
+    Some((first & 0x0f, payload, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        time::Duration,
+    };
+
+    use crate::pinger::{
+        ping_fake_server,
+        websocket::{accept_key, frames, header_end, parse_frame, OPCODE_PING, OPCODE_PONG},
+        Ping, PingError, Session, Websocket,
+    };
+
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while header_end(&request).is_none() {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8(request).unwrap()
+    }
+
+    fn key(request: &str) -> &str {
+        request
+            .lines()
+            .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+            .unwrap()
+    }
+
+    fn ping(ping: bool, answer: fn(&mut TcpStream, &str)) -> Ping {
+        let websocket = Websocket {
+            timeout: Duration::from_millis(500),
+            host: "gateway.example.com".to_owned(),
+            path: "/live".to_owned(),
+            headers: vec![("Origin".to_owned(), "https://example.com".to_owned())],
+            ping,
+        };
+        ping_fake_server(&Session::default(), Some(&websocket), |mut stream| {
+            let request = read_request(&mut stream);
+            answer(&mut stream, &request);
+        })
+        .0
+    }
+
+    fn switching_protocols(stream: &mut TcpStream, request: &str) {
+        let accept = accept_key(key(request));
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {accept}\r\n\r\n"
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // the example of RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let ping = ping(false, |stream, request| {
+            assert!(request.starts_with("GET /live HTTP/1.1\r\n"));
+            assert!(request.contains("\r\nHost: gateway.example.com\r\n"));
+            assert!(request.contains("\r\nOrigin: https://example.com\r\n"));
+            switching_protocols(stream, request);
+        });
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+        assert_eq!(
+            ping.response.unwrap().data,
+            b"HTTP/1.1 101 Switching Protocols"
+        );
+    }
+
+    #[test]
+    fn test_refused() {
+        match ping(false, |stream, _| {
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+        })
+        .err
+        {
+            Some(PingError::Protocol { protocol, reason }) => {
+                assert_eq!(protocol, "websocket");
+                assert_eq!(reason, "replied \"HTTP/1.1 403 Forbidden\"");
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_wrong_accept() {
+        let ping = ping(false, |stream, _| {
+            stream
+                .write_all(
+                    b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                      Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGb+4R0LzBrOo=\r\n\r\n",
+                )
+                .unwrap();
+        });
+        assert!(matches!(ping.err, Some(PingError::Protocol { .. })));
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let ping = ping(true, |stream, request| {
+            switching_protocols(stream, request);
+            // a text frame comes before the pong
+            stream.write_all(b"\x81\x05hello").unwrap();
+            let mut frame = [0; 2 + 4 + 9];
+            stream.read_exact(&mut frame).unwrap();
+            let (opcode, payload, _) = parse_frame(&frame).unwrap();
+            assert_eq!(opcode, OPCODE_PING);
+            let mut pong = vec![0x80 | OPCODE_PONG, payload.len() as u8];
+            pong.extend(payload);
+            stream.write_all(&pong).unwrap();
+        });
+        assert!(ping.err.is_none(), "{:?}", ping.err);
+    }
+
+    #[test]
+    fn test_no_pong() {
+        let ping = ping(true, |stream, request| {
+            switching_protocols(stream, request);
+            stream.read_exact(&mut [0; 2 + 4 + 9]).unwrap();
+            // close with status 1011, internal error
+            stream.write_all(b"\x88\x02\x03\xf3").unwrap();
+        });
+        match ping.err {
+            Some(PingError::Protocol { reason, .. }) => {
+                assert_eq!(reason, "closed with status 1011")
+            }
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_frames() {
+        let data = b"\x81\x02hi\x8a\x00\x82\x7e\x00\x01";
+        assert_eq!(
+            frames(data),
+            [(0x1, b"hi".to_vec()), (OPCODE_PONG, Vec::new())]
+        );
+    }
+}
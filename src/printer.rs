@@ -1,23 +1,131 @@
-use chrono::Local;
+use chrono::{Local, TimeDelta};
 
-use crate::tracker::{Info, Probe};
+use std::{net::SocketAddr, time::Duration};
+
+use crate::{
+    pinger::{sanitize, PingError, Response},
+    target::Protocol,
+    traceroute::Hop,
+    tracker::{Info, Probe},
+    user_input::UserInput,
+};
+
+pub fn print_header(user_input: &UserInput, target: &str) {
+    let ports = match user_input.ports.is_empty() {
+        true => "".to_owned(),
+        false => {
+            let ports: Vec<_> = user_input
+                .ports
+                .iter()
+                .map(|port| port.to_string())
+                .collect();
+            format!(" on port {}", ports.join(","))
+        }
+    };
+    let source = user_input
+        .source
+        .map_or("".to_owned(), |source| format!(" from {source}"));
+    let interface = user_input
+        .interface
+        .as_ref()
+        .map_or("".to_owned(), |interface| format!(" via {interface}"));
+    let source_ports = user_input
+        .source_ports
+        .as_ref()
+        .map_or("".to_owned(), |ports| match ports.start() == ports.end() {
+            true => format!(" from port {}", ports.start()),
+            false => format!(" from ports {}-{}", ports.start(), ports.end()),
+        });
+    let over_udp = match user_input.udp {
+        true => " over UDP",
+        false => "",
+    };
+    let through = user_input
+        .proxy
+        .as_ref()
+        .map_or("".to_owned(), |proxy| format!(" through {proxy}"));
+    println!("TCPing {target}{ports}{over_udp}{through}{source}{source_ports}{interface}")
+}
+
+pub fn print_traceroute_header(user_input: &UserInput, socket: SocketAddr, max_hops: u32) {
+    let url = &user_input.url;
+    let ip_addr = socket.ip();
+    let port = socket.port();
+    println!("TCP traceroute to {url} ({ip_addr}) on port {port}, {max_hops} hops max")
+}
+
+pub fn print_hop(user_input: &UserInput, ttl: u32, hop: &Hop) {
+    match hop {
+        Hop::Silent => println!("{ttl:>3}  *"),
+        Hop::Router { addr, rtt } => println!("{ttl:>3}  {addr}  {:.3} ms", ms(*rtt)),
+        Hop::Unreachable { addr, rtt, code } => println!(
+            "{ttl:>3}  {addr}  {:.3} ms  destination unreachable (code {code})",
+            ms(*rtt)
+        ),
+        Hop::Target { rtt, open } => println!(
+            "{ttl:>3}  {}  {:.3} ms  port {}",
+            user_input.url,
+            ms(*rtt),
+            if *open { "open" } else { "closed" }
+        ),
+    }
+}
 
 pub fn print_probe(info: &Info, probe: &Probe) {
     if probe.err.is_none() {
         print_probe_success(info, probe)
     } else {
-        print_probe_failure(info)
+        print_probe_failure(info, probe)
     }
 }
 
-pub fn print_final_stats(info: &Info) {
-    let url = &info.user_input.url;
-    let ip_addr = &info.ip_addr;
-    let port = info.user_input.port;
+pub fn print_final_stats(infos: &[Info]) {
+    let stats = match infos {
+        [info] => port_stats(info),
+        _ => ports_table(infos),
+    };
+    print!("{stats}");
+    let tcping_start = infos.iter().filter_map(|info| info.start_time).min();
+    let tcping_end = infos.iter().filter_map(|info| info.end_time).max();
+    let tcping_duration = match (tcping_start, tcping_end) {
+        (Some(start), Some(end)) => end - start,
+        _ => TimeDelta::zero(),
+    };
+    let tcping_start = tcping_start
+        .map(|t| t.with_timezone(&Local))
+        .map_or("".to_owned(), |t| {
+            format!("{}", t.format("%Y-%m-%d %H:%M:%S"))
+        });
+    let tcping_end = tcping_end
+        .map(|t| t.with_timezone(&Local))
+        .map_or("".to_owned(), |t| {
+            format!("{}", t.format("%Y-%m-%d %H:%M:%S"))
+        });
+    let tcping_hours = (tcping_duration.num_seconds() / 60) / 60;
+    let tcping_minutes = (tcping_duration.num_seconds() / 60) % 60;
+    let tcping_seconds = tcping_duration.num_seconds() % 60;
+    println!(
+        "
+--------------------------------------
+TCPing started at: {tcping_start}
+TCPing ended at:   {tcping_end}
+duration (HH:MM:SS): {:0>2}:{:0>2}:{:0>2}",
+        tcping_hours, tcping_minutes, tcping_seconds
+    );
+    // the ticks are skipped for all the ports at once
+    let skipped_ticks = infos.iter().map(|info| info.skipped_ticks).max();
+    if let Some(skipped_ticks @ 1..) = skipped_ticks {
+        println!("skipped ticks: {skipped_ticks}, the probes overran the interval");
+    }
+}
+
+fn port_stats(info: &Info) -> String {
+    let target = &info.target;
+    let on_port = on_port(info);
     let succ_counter = info.succ_probes_counter;
     let fail_counter = info.fail_probes_counter;
     let total_probes = succ_counter + fail_counter;
-    let packet_loss_perc = format!("{:.2}", fail_counter as f64 / total_probes as f64 * 100.0);
+    let packet_loss_perc = format!("{:.2}", packet_loss(info));
     let last_succ_probe = info
         .last_succ_probe
         .map(|t| t.with_timezone(&Local))
@@ -30,64 +138,350 @@ pub fn print_final_stats(info: &Info) {
         .map_or("Never failed".to_owned(), |t| {
             format!("{}", t.format("%Y-%m-%d %H:%M:%S"))
         });
-    let total_uptime = info.total_uptime.num_seconds();
-    let total_downtime = info.total_downtime.num_seconds();
-    let tcping_start = info
-        .start_time
-        .map(|t| t.with_timezone(&Local))
-        .map_or("".to_owned(), |t| {
-            format!("{}", t.format("%Y-%m-%d %H:%M:%S"))
-        });
-    let tcping_end = info
-        .end_time
-        .map(|t| t.with_timezone(&Local))
-        .map_or("".to_owned(), |t| {
-            format!("{}", t.format("%Y-%m-%d %H:%M:%S"))
-        });
-    let tcping_duration = info.end_time.unwrap() - info.start_time.unwrap();
-    let tcping_hours = (tcping_duration.num_seconds() / 60) / 60;
-    let tcping_minutes = (tcping_duration.num_seconds() / 60) % 60;
-    let tcping_seconds = tcping_duration.num_seconds() % 60;
-    println!(
-"
---- {url} ({ip_addr}) TCPing statistics ---
-{total_probes} probes transmitted on port {port} | {succ_counter} received, {packet_loss_perc}% packet loss
+    let total_uptime = info.total_uptime.as_secs();
+    let total_downtime = info.total_downtime.as_secs();
+    let mut stats = format!("\n--- {target} TCPing statistics ---\n");
+    stats += &socket_options(info);
+    stats += &format!(
+"{total_probes} probes transmitted{on_port} | {succ_counter} received, {packet_loss_perc}% packet loss
 successful probes:   {succ_counter}
 unsuccessful probes: {fail_counter}
 last successful probe:   {last_succ_probe}
 last unsuccessful probe: {last_fail_probe}
 total uptime:   {total_uptime} seconds
-total downtime: {total_downtime} seconds"
+total downtime: {total_downtime} seconds
+"
     );
-    if succ_counter > 0 {
-        let min = info.min_rtt.num_microseconds().unwrap_or_default() as f32 / 1000.0;
-        let max = info.max_rtt.num_microseconds().unwrap_or_default() as f32 / 1000.0;
-        let avg = info.sum_rtt.num_milliseconds() as f32 / succ_counter as f32;
-        println!("rtt min/avg/max: {:.2}/{:.2}/{:.2} ms", min, avg, max);
+    if info.user_input.hold.is_some() {
+        stats += &format!("dropped early: {}\n", info.early_close_counter);
     }
-    println!(
-        "
---------------------------------------
-TCPing started at: {tcping_start}
-TCPing ended at:   {tcping_end}
-duration (HH:MM:SS): {:0>2}:{:0>2}:{:0>2}",
-        tcping_hours, tcping_minutes, tcping_seconds
+    if info.user_input.protocol != Protocol::Tcp {
+        let tcp_fail_counter = fail_counter - info.protocol_fail_counter;
+        stats += &format!(
+            "failed at the TCP level: {tcp_fail_counter}, at the {} level: {}\n",
+            info.user_input.protocol, info.protocol_fail_counter
+        );
+    }
+    if info.user_input.udp {
+        stats += &format!(
+            "port unreachable: {}, duplicate replies: {}, out of order replies: {}\n",
+            info.unreachable_counter, info.duplicate_replies, info.out_of_order_replies
+        );
+    }
+    if info.slow_connect_counter > 0 {
+        let excluded = match info.user_input.exclude_slow_connects {
+            true => ", left out of the rtt",
+            false => "",
+        };
+        stats += &format!(
+            "slow connect (retransmitted SYN): {}{excluded}\n",
+            info.slow_connect_counter
+        );
+    }
+    if let Some(server) = &info.server {
+        stats += &format!("server: {server}\n");
+    }
+    if info.user_input.protocol != Protocol::Tcp && info.response_counter > 0 {
+        let avg = ms(info.sum_response_time) / info.response_counter as f64;
+        stats += &format!(
+            "{} handshake min/avg/max: {:.3}/{:.3}/{:.3} ms\n",
+            info.user_input.protocol,
+            ms(info.min_response_time),
+            avg,
+            ms(info.max_response_time)
+        );
+    }
+    if info.tcp_info_counter > 0 {
+        let avg = ms(info.sum_kernel_rtt) / info.tcp_info_counter as f64;
+        stats += &format!(
+            "kernel rtt min/avg/max: {:.3}/{:.3}/{:.3} ms, retransmits: {} ({} of SYN)\n",
+            ms(info.min_kernel_rtt),
+            avg,
+            ms(info.max_kernel_rtt),
+            info.retransmits,
+            info.syn_retransmits
+        );
+    }
+    if let Some(avg) = avg_rtt(info) {
+        stats += &format!(
+            "rtt min/avg/max: {:.3}/{:.3}/{:.3} ms\n",
+            ms(info.min_rtt),
+            avg,
+            ms(info.max_rtt)
+        );
+    }
+    stats
+}
+
+/// One line per port with the probes sent and received, the packet loss, the
+/// average rtt and whether the last probe succeeded.
+fn ports_table(infos: &[Info]) -> String {
+    let Some(first) = infos.first() else {
+        return "".to_owned();
+    };
+    let target = &first.target;
+    let mut table = format!("\n--- {target} TCPing statistics ---\n");
+    table += &socket_options(first);
+    table += &format!(
+        "{:>5} {:>8} {:>8} {:>8} {:>11}  last state\n",
+        "port", "sent", "received", "loss", "avg rtt"
     );
+    for info in infos {
+        let sent = info.succ_probes_counter + info.fail_probes_counter;
+        let loss = format!("{:.2}%", packet_loss(info));
+        let avg = avg_rtt(info).map_or("-".to_owned(), |avg| format!("{avg:.3} ms"));
+        let last_state = if info.succ_probes_streak > 0 {
+            "up"
+        } else if info.fail_probes_streak > 0 {
+            "down"
+        } else {
+            "-"
+        };
+        table += &format!(
+            "{:>5} {:>8} {:>8} {:>8} {:>11}  {last_state}\n",
+            info.port, sent, info.succ_probes_counter, loss, avg
+        );
+    }
+    table
+}
+
+/// The socket options of the probes, if any was asked.
+fn socket_options(info: &Info) -> String {
+    match info.socket_options.is_empty() {
+        true => "".to_owned(),
+        false => format!("socket options: {}\n", info.socket_options.join(", ")),
+    }
+}
+
+fn packet_loss(info: &Info) -> f64 {
+    let total_probes = info.succ_probes_counter + info.fail_probes_counter;
+    info.fail_probes_counter as f64 / total_probes as f64 * 100.0
+}
+
+fn avg_rtt(info: &Info) -> Option<f64> {
+    match info.rtt_counter() {
+        0 => None,
+        rtt_counter => Some(ms(info.sum_rtt) / rtt_counter as f64),
+    }
+}
+
+/// The duration in milliseconds, down to the microsecond.
+fn ms(t: Duration) -> f64 {
+    t.as_micros() as f64 / 1000.0
 }
 
 fn print_probe_success(info: &Info, probe: &Probe) {
-    let url = &info.user_input.url;
-    let ip_addr = info.ip_addr;
-    let port = info.user_input.port;
-    let elapsed = probe.elapsed.num_milliseconds();
+    let destination = destination(info);
+    let source_port = source_port(info, probe);
+    let elapsed = format!("{:.3}", ms(probe.elapsed));
     let counter = info.succ_probes_streak;
-    println!("Reply from {url} ({ip_addr}) on port {port} TCP_conn={counter} time={elapsed} ms")
+    let conn = conn_label(info);
+    let proxy_time = probe
+        .proxy_time
+        .map_or("".to_owned(), |t| format!(" proxy_time={:.3} ms", ms(t)));
+    let banner = response("banner", probe.banner.as_ref());
+    let response = response("response", probe.response.as_ref());
+    let tcp_info = tcp_info(probe);
+    let slow_connect = match info.last_slow_connect {
+        true => " (retransmitted SYN)",
+        false => "",
+    };
+    println!("Reply from {destination}{source_port} {conn}={counter} time={elapsed} ms{slow_connect}{proxy_time}{banner}{response}{tcp_info}")
 }
 
-fn print_probe_failure(info: &Info) {
-    let url = &info.user_input.url;
-    let ip_addr = info.ip_addr;
-    let port = info.user_input.port;
+fn print_probe_failure(info: &Info, probe: &Probe) {
+    let destination = destination(info);
+    let source_port = source_port(info, probe);
     let counter = info.fail_probes_streak;
-    println!("No reply from {url} ({ip_addr}) on port {port} TCP_conn={counter}")
+    let conn = conn_label(info);
+    match &probe.err {
+        Some(PingError::Connect(_)) | None => {
+            println!("No reply from {destination}{source_port} {conn}={counter}")
+        }
+        Some(PingError::NoResponse(_)) if info.user_input.udp => {
+            println!("No reply from {destination}{source_port} {conn}={counter}")
+        }
+        Some(err) if info.user_input.udp => {
+            println!("Datagram to {destination}{source_port} {err} {conn}={counter}")
+        }
+        Some(err) => println!("Connection to {destination}{source_port} {err} {conn}={counter}"),
+    }
+}
+
+/// The name of the streak counter of the probe lines.
+fn conn_label(info: &Info) -> &'static str {
+    match info.user_input.udp {
+        true => "UDP_seq",
+        false => "TCP_conn",
+    }
+}
+
+/// The port probed, when the target has ports.
+fn on_port(info: &Info) -> String {
+    match info.user_input.ports.is_empty() {
+        true => "".to_owned(),
+        false => format!(" on port {}", info.port),
+    }
+}
+
+/// The target, with its address and the port probed.
+fn destination(info: &Info) -> String {
+    format!("{}{}", info.target, on_port(info))
+}
+
+fn response(name: &str, response: Option<&Response>) -> String {
+    response.map_or("".to_owned(), |response| {
+        format!(
+            " {name}_time={:.3} ms {name}=\"{}\"",
+            ms(response.time),
+            sanitize(&response.data)
+        )
+    })
+}
+
+/// The kernel RTT and retransmits of the probe, when its TCP_INFO was read.
+fn tcp_info(probe: &Probe) -> String {
+    let Some(tcp_info) = probe.exchange_tcp_info.or(probe.tcp_info) else {
+        return "".to_owned();
+    };
+    format!(
+        " kernel_rtt={:.3} ms retrans={}",
+        ms(tcp_info.rtt),
+        tcp_info.retransmits
+    )
+}
+
+/// The source port of the probe, shown only when the user chose the source
+/// ports, as it is what tells apart the connections.
+fn source_port(info: &Info, probe: &Probe) -> String {
+    match (&info.user_input.source_ports, probe.source_port) {
+        (Some(_), Some(source_port)) => format!(" from port {source_port}"),
+        _ => "".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        pinger::{Response, TcpInfo},
+        printer::{ms, port_stats, ports_table, response},
+        tracker::{tests::ProbeBuilder, Info},
+        user_input::UserInput,
+    };
+
+    fn info(port: u16, rtts: &[u64], failures: u128) -> Info {
+        let mut info = Info::new(
+            UserInput {
+                ports: vec![22, 80, 443],
+                ..Default::default()
+            },
+            "example.com (93.184.216.34)".to_owned(),
+            port,
+        );
+        for &rtt in rtts {
+            let rtt = Duration::from_micros(rtt);
+            info.min_rtt = match info.succ_probes_counter {
+                0 => rtt,
+                _ => info.min_rtt.min(rtt),
+            };
+            info.max_rtt = info.max_rtt.max(rtt);
+            info.sum_rtt += rtt;
+            info.succ_probes_counter += 1;
+        }
+        info.fail_probes_counter = failures;
+        match failures {
+            0 => info.succ_probes_streak = rtts.len() as u128,
+            _ => info.fail_probes_streak = failures,
+        }
+        info
+    }
+
+    #[test]
+    fn test_ports_table() {
+        let infos = [
+            info(22, &[1500, 2500], 0),
+            info(80, &[], 3),
+            info(443, &[12_345], 1),
+        ];
+        assert_eq!(
+            ports_table(&infos),
+            "
+--- example.com (93.184.216.34) TCPing statistics ---
+ port     sent received     loss     avg rtt  last state
+   22        2        2    0.00%    2.000 ms  up
+   80        3        0  100.00%           -  down
+  443        2        1   50.00%   12.345 ms  down
+"
+        );
+        assert_eq!(ports_table(&[]), "");
+    }
+
+    #[test]
+    fn test_port_stats() {
+        let mut up = info(443, &[250, 1750], 2);
+        up.socket_options = vec!["TCP_NODELAY".to_owned()];
+        let stats = port_stats(&up);
+        assert!(stats.starts_with(
+            "
+--- example.com (93.184.216.34) TCPing statistics ---
+socket options: TCP_NODELAY
+4 probes transmitted on port 443 | 2 received, 50.00% packet loss
+"
+        ));
+        assert!(stats.ends_with("rtt min/avg/max: 0.250/1.000/1.750 ms\n"));
+
+        let stats = port_stats(&info(443, &[], 4));
+        assert!(
+            stats.contains("4 probes transmitted on port 443 | 0 received, 100.00% packet loss\n")
+        );
+        assert!(stats.contains("last successful probe:   Never succeded\n"));
+        assert!(!stats.contains("rtt min/avg/max"));
+    }
+
+    #[test]
+    fn test_ms() {
+        assert_eq!(ms(Duration::from_nanos(999)), 0.0);
+        assert_eq!(format!("{:.3}", ms(Duration::from_micros(250))), "0.250");
+        assert_eq!(
+            format!("{:.3}", ms(Duration::from_nanos(1_999_999))),
+            "1.999"
+        );
+        assert_eq!(
+            format!("{:.3}", ms(Duration::new(3, 250_123_999))),
+            "3250.123"
+        );
+        assert_eq!(format!("{:.3}", ms(Duration::from_secs(75))), "75000.000");
+    }
+
+    #[test]
+    fn test_tcp_info() {
+        let tcp_info = |rtt| TcpInfo {
+            rtt,
+            rtt_var: Duration::ZERO,
+            retransmits: 1,
+            mss: 1448,
+        };
+        let mut probe = ProbeBuilder::new().build();
+        assert_eq!(super::tcp_info(&probe), "");
+        probe.tcp_info = Some(tcp_info(Duration::from_micros(87)));
+        assert_eq!(super::tcp_info(&probe), " kernel_rtt=0.087 ms retrans=1");
+        // the one after the exchange is shown when there is one
+        probe.exchange_tcp_info = Some(tcp_info(Duration::from_millis(2_040)));
+        assert_eq!(super::tcp_info(&probe), " kernel_rtt=2040.000 ms retrans=1");
+    }
+
+    #[test]
+    fn test_response() {
+        assert_eq!(response("banner", None), "");
+        let pong = Response {
+            data: b"+PONG\r\n\r\n".to_vec(),
+            time: Duration::from_micros(430),
+        };
+        assert_eq!(
+            response("response", Some(&pong)),
+            " response_time=0.430 ms response=\"+PONG\""
+        );
+    }
 }
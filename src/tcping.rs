@@ -1,47 +1,249 @@
 use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
     sync::mpsc::{Receiver, Sender},
     thread,
+    time::{Duration, Instant},
 };
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 
 use crate::{pinger::Pinger, tracker::Probe, user_input::UserInput};
 
-pub fn tcping<P: Pinger + ?Sized>(
+/// Where the probes read the time and wait between cycles, so that the tests
+/// can drive it.
+pub trait Clock {
+    /// The monotonic time, which the durations are measured with.
+    fn now(&self) -> Instant;
+    /// The wall clock time, which the probes are timestamped with.
+    fn wall(&self) -> DateTime<Utc>;
+    /// Waits for `timeout`, or less if the thread is unparked.
+    fn park_timeout(&self, timeout: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn park_timeout(&self, timeout: Duration) {
+        thread::park_timeout(timeout)
+    }
+}
+
+/// When the cycles of probes start: every `interval` from the first one, so
+/// that slow probes do not shift the following ones. The ticks a cycle
+/// overruns are skipped.
+pub struct Schedule {
+    first: Instant,
+    interval: Duration,
+    /// The fraction of the interval the ticks are spread over, around their
+    /// fixed-rate time.
+    jitter: f64,
+    /// The index of the next tick.
+    index: u64,
+    /// When the next tick is, its jitter included.
+    next: Instant,
+}
+
+impl Schedule {
+    /// A schedule whose first tick is at `first`, moved by the jitter as
+    /// `random`, from 0 to 1, tells.
+    pub fn new(first: Instant, interval: Duration, jitter: f64, random: f64) -> Schedule {
+        let mut schedule = Schedule {
+            first,
+            interval,
+            jitter,
+            index: 0,
+            next: first,
+        };
+        schedule.next = schedule.tick(0, random);
+        schedule
+    }
+
+    /// When the next tick is.
+    pub fn next(&self) -> Instant {
+        self.next
+    }
+
+    /// Moves to the first tick after `now`, returning how many were skipped
+    /// on the way.
+    pub fn advance(&mut self, now: Instant, random: f64) -> u64 {
+        let mut skipped = 0;
+        loop {
+            self.index += 1;
+            self.next = self.tick(self.index, random);
+            if self.next >= now || self.interval.is_zero() {
+                return skipped;
+            }
+            skipped += 1;
+        }
+    }
+
+    fn tick(&self, index: u64, random: f64) -> Instant {
+        let tick =
+            self.first + Duration::from_nanos((self.interval.as_nanos() * index as u128) as u64);
+        let spread = self.interval.mul_f64(self.jitter);
+        (tick + spread.mul_f64(random))
+            .checked_sub(spread / 2)
+            .unwrap_or(tick)
+    }
+}
+
+/// How long until the next multiple of `interval` since the epoch, by the
+/// wall clock.
+fn until_aligned(wall: DateTime<Utc>, interval: Duration) -> Duration {
+    let interval = interval.as_micros() as i64;
+    if interval == 0 {
+        return Duration::ZERO;
+    }
+    let past = wall.timestamp_micros().rem_euclid(interval);
+    Duration::from_micros(((interval - past) % interval) as u64)
+}
+
+/// A random number from 0 to 1.
+fn random() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+/// Probes every port once per cycle, so each `(port, pinger)` pair receives
+/// `user_input.probes_count` probes. The cycles follow a `Schedule`, aligned
+/// on the wall clock if asked.
+pub fn tcping<P: Pinger + ?Sized, C: Clock + ?Sized>(
     probe_sx: Sender<Probe>,
     closer_rx: Receiver<()>,
-    pinger: &P,
+    pingers: &[(u16, &P)],
     user_input: UserInput,
+    clock: &C,
 ) {
+    let interval = user_input
+        .interval_between_probes
+        .to_std()
+        .unwrap_or_default();
+    let first = match user_input.interval_align {
+        true => clock.now() + until_aligned(clock.wall(), interval),
+        false => clock.now(),
+    };
+    let jitter = user_input.interval_jitter;
+    let mut schedule = Schedule::new(first, interval, jitter, random());
+    if let Some(wait) = schedule.next().checked_duration_since(clock.now()) {
+        clock.park_timeout(wait);
+    }
     let mut i = 0;
-    while closer_rx.try_recv().is_err() && user_input.probes_count.map_or(true, |c| i < c) {
-        let start = Utc::now();
-        let err = pinger.ping();
-        let elapsed = Utc::now() - start;
-        if elapsed < user_input.interval_between_probes {
-            thread::park_timeout(
-                (user_input.interval_between_probes - elapsed)
-                    .to_std()
-                    .unwrap(),
-            );
+    while closer_rx.try_recv().is_err() && user_input.probes_count.is_none_or(|c| i < c) {
+        let cycle_start = clock.now();
+        let mut probes = Vec::with_capacity(pingers.len());
+        for (port, pinger) in pingers {
+            let start = clock.wall();
+            let probe_start = clock.now();
+            let ping = pinger.ping();
+            let elapsed = ping
+                .connect_time
+                .unwrap_or_else(|| clock.now() - probe_start);
+            probes.push((*port, start, elapsed, ping));
+        }
+        let skipped_ticks = schedule.advance(clock.now(), random());
+        if let Some(wait) = schedule.next().checked_duration_since(clock.now()) {
+            clock.park_timeout(wait);
+        }
+        let cycle_duration = clock.now() - cycle_start;
+        for (port, start, elapsed, ping) in probes {
+            _ = probe_sx.send(Probe {
+                port,
+                source_port: ping.source_port,
+                banner: ping.banner,
+                response: ping.response,
+                server: ping.server,
+                proxy_time: ping.proxy_time,
+                duplicate_replies: ping.duplicate_replies,
+                out_of_order_replies: ping.out_of_order_replies,
+                tcp_info: ping.tcp_info,
+                exchange_tcp_info: ping.exchange_tcp_info,
+                elapsed,
+                err: ping.err,
+                start,
+                cycle_duration,
+                skipped_ticks,
+            });
         }
-        _ = probe_sx.send(Probe {
-            elapsed,
-            err,
-            start,
-            cycle_duration: Utc::now() - start,
-        });
         i += 1;
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{sync::mpsc::channel, thread};
+    use std::{
+        cell::Cell,
+        sync::mpsc::channel,
+        thread,
+        time::{Duration as StdDuration, Instant},
+    };
+
+    use chrono::{DateTime, Duration, Utc};
+
+    use crate::{
+        pinger::{Ping, Pinger},
+        target::Protocol,
+        tcping::{tcping, until_aligned, Clock, Schedule, SystemClock},
+        user_input::UserInput,
+    };
+
+    /// A clock that only moves when waiting, or when a pinger advances it.
+    struct FakeClock {
+        origin: Instant,
+        wall_origin: DateTime<Utc>,
+        elapsed: Cell<StdDuration>,
+    }
+
+    impl FakeClock {
+        fn new() -> FakeClock {
+            FakeClock {
+                origin: Instant::now(),
+                wall_origin: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                elapsed: Cell::new(StdDuration::ZERO),
+            }
+        }
+
+        fn advance(&self, by: StdDuration) {
+            self.elapsed.set(self.elapsed.get() + by);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.origin + self.elapsed.get()
+        }
+
+        fn wall(&self) -> DateTime<Utc> {
+            self.wall_origin + Duration::from_std(self.elapsed.get()).unwrap()
+        }
+
+        fn park_timeout(&self, timeout: StdDuration) {
+            self.advance(timeout)
+        }
+    }
 
-    use chrono::Duration;
+    /// Takes the given times to answer, in turn.
+    struct SlowPinger<'a> {
+        clock: &'a FakeClock,
+        delays: Vec<StdDuration>,
+        next: Cell<usize>,
+    }
 
-    use crate::{pinger::Pinger, tcping::tcping, user_input::UserInput};
+    impl Pinger for SlowPinger<'_> {
+        fn ping(&self) -> Ping {
+            let i = self.next.get();
+            self.next.set(i + 1);
+            self.clock.advance(self.delays[i % self.delays.len()]);
+            Ping::default()
+        }
+    }
 
     struct UserInputBuilder {
         user_input: UserInput,
@@ -52,10 +254,12 @@ mod tests {
             UserInputBuilder {
                 user_input: UserInput {
                     url,
-                    port,
+                    ports: vec![port],
+                    protocol: Protocol::Tcp,
                     timeout: None,
                     probes_count: None,
                     interval_between_probes: Duration::seconds(1),
+                    ..Default::default()
                 },
             }
         }
@@ -70,6 +274,11 @@ mod tests {
             self
         }
 
+        fn interval_align(mut self) -> Self {
+            self.user_input.interval_align = true;
+            self
+        }
+
         fn build(self) -> UserInput {
             self.user_input
         }
@@ -82,15 +291,21 @@ mod tests {
         let (_, closer_rx) = channel();
         struct MockPinger;
         impl Pinger for MockPinger {
-            fn ping(&self) -> Option<std::io::Error> {
-                None
+            fn ping(&self) -> Ping {
+                Ping::default()
             }
         }
         let user_input = UserInputBuilder::new("1.2.3.4".to_owned(), 443)
             .probes_count(PROBES_COUNT)
             .interval_between_probes(Duration::zero())
             .build();
-        tcping(probe_sx, closer_rx, &MockPinger, user_input);
+        tcping(
+            probe_sx,
+            closer_rx,
+            &[(443, &MockPinger)],
+            user_input,
+            &SystemClock,
+        );
         thread::spawn(move || {
             let mut i = 0;
             while probe_rx.try_recv().is_ok() {
@@ -101,4 +316,210 @@ mod tests {
         .join()
         .expect("join failed");
     }
+
+    #[test]
+    fn test_probes_count_per_port() {
+        const PROBES_COUNT: u128 = 10;
+        let (probe_sx, probe_rx) = channel();
+        let (_, closer_rx) = channel();
+        struct MockPinger;
+        impl Pinger for MockPinger {
+            fn ping(&self) -> Ping {
+                Ping::default()
+            }
+        }
+        let user_input = UserInputBuilder::new("1.2.3.4".to_owned(), 80)
+            .probes_count(PROBES_COUNT)
+            .interval_between_probes(Duration::zero())
+            .build();
+        let pingers = [(80, &MockPinger), (443, &MockPinger), (8080, &MockPinger)];
+        tcping(probe_sx, closer_rx, &pingers, user_input, &SystemClock);
+        let ports: Vec<u16> = probe_rx.try_iter().map(|probe| probe.port).collect();
+        assert_eq!(ports.len() as u128, 3 * PROBES_COUNT);
+        for port in [80, 443, 8080] {
+            assert_eq!(
+                ports.iter().filter(|p| **p == port).count() as u128,
+                PROBES_COUNT
+            );
+        }
+        assert_eq!(ports[..3], [80, 443, 8080]);
+    }
+
+    #[test]
+    fn test_timing() {
+        let (probe_sx, probe_rx) = channel();
+        let (_, closer_rx) = channel();
+        let clock = FakeClock::new();
+        let pinger = SlowPinger {
+            clock: &clock,
+            delays: vec![
+                StdDuration::from_micros(1250),
+                StdDuration::from_millis(1500),
+            ],
+            next: Cell::new(0),
+        };
+        let user_input = UserInputBuilder::new("1.2.3.4".to_owned(), 443)
+            .probes_count(3)
+            .build();
+        tcping(probe_sx, closer_rx, &[(443, &pinger)], user_input, &clock);
+        let probes: Vec<_> = probe_rx.try_iter().collect();
+        let elapsed: Vec<_> = probes.iter().map(|probe| probe.elapsed).collect();
+        assert_eq!(
+            elapsed,
+            [
+                StdDuration::from_micros(1250),
+                StdDuration::from_millis(1500),
+                StdDuration::from_micros(1250)
+            ]
+        );
+        // a probe slower than the interval skips the tick it overran, and
+        // the next cycle starts on the following one
+        let cycles: Vec<_> = probes.iter().map(|probe| probe.cycle_duration).collect();
+        assert_eq!(
+            cycles,
+            [
+                StdDuration::from_secs(1),
+                StdDuration::from_secs(2),
+                StdDuration::from_secs(1)
+            ]
+        );
+        let skipped: Vec<_> = probes.iter().map(|probe| probe.skipped_ticks).collect();
+        assert_eq!(skipped, [0, 1, 0]);
+        let starts: Vec<_> = probes
+            .iter()
+            .map(|probe| probe.start - clock.wall_origin)
+            .collect();
+        assert_eq!(
+            starts,
+            [Duration::zero(), Duration::seconds(1), Duration::seconds(3)]
+        );
+    }
+
+    #[test]
+    fn test_timing_connect_time() {
+        let (probe_sx, probe_rx) = channel();
+        let (_, closer_rx) = channel();
+        let clock = FakeClock::new();
+        struct ConnectPinger<'a>(&'a FakeClock);
+        impl Pinger for ConnectPinger<'_> {
+            fn ping(&self) -> Ping {
+                // the handshake after the connection is not part of the rtt
+                self.0.advance(StdDuration::from_millis(30));
+                Ping {
+                    connect_time: Some(StdDuration::from_micros(10_500)),
+                    ..Default::default()
+                }
+            }
+        }
+        let user_input = UserInputBuilder::new("1.2.3.4".to_owned(), 443)
+            .probes_count(1)
+            .build();
+        tcping(
+            probe_sx,
+            closer_rx,
+            &[(443, &ConnectPinger(&clock))],
+            user_input,
+            &clock,
+        );
+        let probe = probe_rx.try_recv().unwrap();
+        assert_eq!(probe.elapsed, StdDuration::from_micros(10_500));
+        assert_eq!(probe.cycle_duration, StdDuration::from_secs(1));
+    }
+
+    /// Ticks without drift, whatever the time the probes take.
+    #[test]
+    fn test_timing_no_drift() {
+        let (probe_sx, probe_rx) = channel();
+        let (_, closer_rx) = channel();
+        let clock = FakeClock::new();
+        let pinger = SlowPinger {
+            clock: &clock,
+            delays: vec![StdDuration::from_millis(300), StdDuration::from_millis(10)],
+            next: Cell::new(0),
+        };
+        let user_input = UserInputBuilder::new("1.2.3.4".to_owned(), 443)
+            .probes_count(100)
+            .build();
+        tcping(probe_sx, closer_rx, &[(443, &pinger)], user_input, &clock);
+        let last = probe_rx.try_iter().last().unwrap();
+        assert_eq!(last.start - clock.wall_origin, Duration::seconds(99));
+        assert_eq!(clock.now() - clock.origin, StdDuration::from_secs(100));
+    }
+
+    #[test]
+    fn test_timing_aligned() {
+        let (probe_sx, probe_rx) = channel();
+        let (_, closer_rx) = channel();
+        let clock = FakeClock {
+            wall_origin: DateTime::from_timestamp(1_700_000_000, 250_000_000).unwrap(),
+            ..FakeClock::new()
+        };
+        let pinger = SlowPinger {
+            clock: &clock,
+            delays: vec![StdDuration::from_millis(10)],
+            next: Cell::new(0),
+        };
+        let user_input = UserInputBuilder::new("1.2.3.4".to_owned(), 443)
+            .probes_count(2)
+            .interval_align()
+            .build();
+        tcping(probe_sx, closer_rx, &[(443, &pinger)], user_input, &clock);
+        let starts: Vec<_> = probe_rx.try_iter().map(|probe| probe.start).collect();
+        assert_eq!(
+            starts,
+            [
+                DateTime::from_timestamp(1_700_000_001, 0).unwrap(),
+                DateTime::from_timestamp(1_700_000_002, 0).unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_until_aligned() {
+        let second = StdDuration::from_secs(1);
+        let wall = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(until_aligned(wall, second), StdDuration::ZERO);
+        let wall = DateTime::from_timestamp(1_700_000_000, 400_000_000).unwrap();
+        assert_eq!(until_aligned(wall, second), StdDuration::from_millis(600));
+        assert_eq!(
+            until_aligned(wall, StdDuration::from_secs(60)),
+            StdDuration::from_millis(39_600)
+        );
+        assert_eq!(until_aligned(wall, StdDuration::ZERO), StdDuration::ZERO);
+    }
+
+    #[test]
+    fn test_schedule_jitter() {
+        let origin = Instant::now();
+        let second = StdDuration::from_secs(1);
+        // spread over half the interval, around the ticks
+        let mut schedule = Schedule::new(origin, second, 0.5, 1.0);
+        assert_eq!(schedule.next() - origin, StdDuration::from_millis(250));
+        assert_eq!(schedule.advance(origin, 0.0), 0);
+        assert_eq!(schedule.next() - origin, StdDuration::from_millis(750));
+        assert_eq!(schedule.advance(origin, 0.5), 0);
+        assert_eq!(schedule.next() - origin, StdDuration::from_secs(2));
+        // the jitter does not accumulate
+        for _ in 0..7 {
+            schedule.advance(origin, 1.0);
+        }
+        assert_eq!(schedule.next() - origin, StdDuration::from_millis(9250));
+    }
+
+    #[test]
+    fn test_schedule_skipped() {
+        let origin = Instant::now();
+        let second = StdDuration::from_secs(1);
+        let mut schedule = Schedule::new(origin, second, 0.0, 0.0);
+        assert_eq!(
+            schedule.advance(origin + StdDuration::from_millis(3500), 0.0),
+            3
+        );
+        assert_eq!(schedule.next() - origin, StdDuration::from_secs(4));
+        // a probe ending right on the tick does not skip it
+        assert_eq!(schedule.advance(origin + StdDuration::from_secs(5), 0.0), 0);
+        assert_eq!(schedule.next() - origin, StdDuration::from_secs(5));
+        let mut schedule = Schedule::new(origin, StdDuration::ZERO, 0.0, 0.0);
+        assert_eq!(schedule.advance(origin + second, 0.0), 0);
+    }
 }
@@ -0,0 +1,320 @@
+//! TCP traceroute: connections to the target port are attempted with an
+//! increasing TTL, and the ICMP errors sent back by the routers along the path
+//! tell where the connection attempts are dropped.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    os::fd::AsFd,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::pinger::{start_connect, wait_connect, Bind, Wake};
+
+/// What was seen at a given TTL.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Hop {
+    /// Nothing answered within the timeout.
+    Silent,
+    /// A router answered that the TTL expired.
+    Router { addr: IpAddr, rtt: Duration },
+    /// A router or the target answered that the destination is unreachable,
+    /// as firewalls rejecting the connection do.
+    Unreachable {
+        addr: IpAddr,
+        rtt: Duration,
+        code: u8,
+    },
+    /// The target accepted (open) or refused (closed) the connection.
+    Target { rtt: Duration, open: bool },
+}
+
+impl Hop {
+    /// Whether the hops after this one cannot be reached.
+    pub fn is_last(&self) -> bool {
+        matches!(self, Hop::Unreachable { .. } | Hop::Target { .. })
+    }
+}
+
+pub struct Traceroute {
+    target: SocketAddr,
+    timeout: Duration,
+    bind: Bind,
+    /// The raw ICMP socket, used through the datagram API of `UdpSocket`.
+    icmp: UdpSocket,
+}
+
+impl Traceroute {
+    /// Opens the raw socket receiving the ICMP errors, which requires CAP_NET_RAW.
+    pub fn new(target: SocketAddr, timeout: Duration, bind: Bind) -> io::Result<Traceroute> {
+        let (domain, protocol) = match target {
+            SocketAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            SocketAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+        let icmp = Socket::new(domain, Type::RAW, Some(protocol))?;
+        // the connection is waited for along with the ICMP errors
+        icmp.set_nonblocking(true)?;
+        Ok(Traceroute {
+            target,
+            timeout,
+            bind,
+            icmp: icmp.into(),
+        })
+    }
+
+    /// Attempts a connection with the given TTL and reports who answered.
+    pub fn probe(&self, ttl: u32) -> io::Result<Hop> {
+        let bind = Bind {
+            ttl: Some(ttl),
+            ..self.bind.clone()
+        };
+        let socket = bind.socket(&self.target, None)?;
+        let start = Instant::now();
+        if start_connect(&socket, &self.target)? {
+            return Ok(Hop::Target {
+                rtt: start.elapsed(),
+                open: true,
+            });
+        }
+        let source_port = socket
+            .local_addr()?
+            .as_socket()
+            .map_or(0, |addr| addr.port());
+        let deadline = start + self.timeout;
+        let mut buf = [0; 1500];
+        loop {
+            match wait_connect(&socket, Some(deadline), Some(self.icmp.as_fd())) {
+                Ok(Wake::Connected) => {
+                    return Ok(Hop::Target {
+                        rtt: start.elapsed(),
+                        open: true,
+                    })
+                }
+                Ok(Wake::Readable) => {}
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    return Ok(Hop::Target {
+                        rtt: start.elapsed(),
+                        open: false,
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(Hop::Silent),
+                Err(e) => return Err(e),
+            }
+            let (n, from) = match self.icmp.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            let Some(error) = IcmpError::parse(&buf[..n], from.ip()) else {
+                continue;
+            };
+            if error.source_port != source_port || error.destination != self.target {
+                continue;
+            }
+            let (addr, rtt) = (from.ip(), start.elapsed());
+            return Ok(match error.kind {
+                IcmpErrorKind::TimeExceeded => Hop::Router { addr, rtt },
+                IcmpErrorKind::Unreachable(code) => Hop::Unreachable { addr, rtt, code },
+            });
+        }
+    }
+}
+
+/// Probes the path hop by hop until the target answers, `max_hops` is reached
+/// or the closer channel is signaled.
+pub fn traceroute(
+    tracer: &Traceroute,
+    max_hops: u32,
+    closer_rx: &Receiver<()>,
+    mut on_hop: impl FnMut(u32, &Hop),
+) -> io::Result<()> {
+    for ttl in 1..=max_hops {
+        if closer_rx.try_recv().is_ok() {
+            break;
+        }
+        let hop = tracer.probe(ttl)?;
+        on_hop(ttl, &hop);
+        if hop.is_last() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum IcmpErrorKind {
+    TimeExceeded,
+    Unreachable(u8),
+}
+
+/// An ICMP error about a TCP segment we sent.
+#[derive(Debug, PartialEq, Eq)]
+struct IcmpError {
+    kind: IcmpErrorKind,
+    /// The source port of the segment that caused the error.
+    source_port: u16,
+    /// The destination of the segment that caused the error.
+    destination: SocketAddr,
+}
+
+impl IcmpError {
+    /// Parses a packet read from the raw socket: IPv4 raw sockets return the
+    /// IP header too, ICMPv6 ones only the ICMP message. The message quotes
+    /// the IP header and the first bytes of the segment that caused it.
+    fn parse(packet: &[u8], from: IpAddr) -> Option<IcmpError> {
+        let (kind, destination, segment) = match from {
+            IpAddr::V4(_) => {
+                let icmp = packet.get((*packet.first()? & 0x0f) as usize * 4..)?;
+                let kind = match (*icmp.first()?, *icmp.get(1)?) {
+                    (11, _) => IcmpErrorKind::TimeExceeded,
+                    (3, code) => IcmpErrorKind::Unreachable(code),
+                    _ => return None,
+                };
+                let quoted = icmp.get(8..)?;
+                if *quoted.get(9)? != libc::IPPROTO_TCP as u8 {
+                    return None;
+                }
+                let destination: [u8; 4] = quoted.get(16..20)?.try_into().ok()?;
+                let segment = quoted.get((*quoted.first()? & 0x0f) as usize * 4..)?;
+                (kind, IpAddr::from(destination), segment)
+            }
+            IpAddr::V6(_) => {
+                let kind = match (*packet.first()?, *packet.get(1)?) {
+                    (3, _) => IcmpErrorKind::TimeExceeded,
+                    (1, code) => IcmpErrorKind::Unreachable(code),
+                    _ => return None,
+                };
+                let quoted = packet.get(8..)?;
+                if *quoted.get(6)? != libc::IPPROTO_TCP as u8 {
+                    return None;
+                }
+                let destination: [u8; 16] = quoted.get(24..40)?.try_into().ok()?;
+                (kind, IpAddr::from(destination), quoted.get(40..)?)
+            }
+        };
+        let port = |i: usize| Some(u16::from_be_bytes([*segment.get(i)?, *segment.get(i + 1)?]));
+        Some(IcmpError {
+            kind,
+            source_port: port(0)?,
+            destination: SocketAddr::new(destination, port(2)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::{SocketAddr, TcpListener},
+        sync::mpsc::channel,
+        time::Duration,
+    };
+
+    use crate::{
+        pinger::Bind,
+        traceroute::{traceroute, Hop, IcmpError, IcmpErrorKind, Traceroute},
+    };
+
+    fn quoted_ipv4(destination: [u8; 4]) -> Vec<u8> {
+        let mut header = vec![0x45, 0, 0, 44, 0, 0, 0, 0, 1, 6, 0, 0, 10, 0, 0, 1];
+        header.extend(destination);
+        header
+    }
+
+    #[test]
+    fn test_parse_time_exceeded_ipv4() {
+        let mut packet = quoted_ipv4([192, 168, 1, 1]);
+        packet.extend([11, 0, 0, 0, 0, 0, 0, 0]);
+        packet.extend(quoted_ipv4([93, 184, 216, 34]));
+        packet.extend([0x9c, 0x40, 0x01, 0xbb, 0, 0, 0, 1]);
+        let error = IcmpError::parse(&packet, "192.168.1.1".parse().unwrap()).unwrap();
+        assert_eq!(
+            error,
+            IcmpError {
+                kind: IcmpErrorKind::TimeExceeded,
+                source_port: 40000,
+                destination: "93.184.216.34:443".parse().unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_unreachable_ipv6() {
+        let mut packet = vec![1, 1, 0, 0, 0, 0, 0, 0];
+        let mut quoted = vec![0x60, 0, 0, 0, 0, 20, 6, 1];
+        quoted.extend(
+            "2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        quoted.extend(
+            "2001:db8::2"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        packet.extend(quoted);
+        packet.extend([0x9c, 0x40, 0x00, 0x16, 0, 0, 0, 1]);
+        let error = IcmpError::parse(&packet, "2001:db8::ff".parse().unwrap()).unwrap();
+        assert_eq!(error.kind, IcmpErrorKind::Unreachable(1));
+        assert_eq!(error.source_port, 40000);
+        assert_eq!(error.destination, "[2001:db8::2]:22".parse().unwrap());
+    }
+
+    #[test]
+    fn test_parse_not_an_error() {
+        let mut packet = quoted_ipv4([10, 0, 0, 1]);
+        // echo reply
+        packet.extend([0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(IcmpError::parse(&packet, "10.0.0.2".parse().unwrap()).is_none());
+        assert!(IcmpError::parse(&[], "10.0.0.2".parse().unwrap()).is_none());
+    }
+
+    /// Raw sockets need CAP_NET_RAW: without it the test is skipped.
+    #[test]
+    fn test_traceroute_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target: SocketAddr = listener.local_addr().unwrap();
+        let tracer = match Traceroute::new(target, Duration::from_secs(1), Bind::default()) {
+            Ok(tracer) => tracer,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(e) => panic!("cannot open the raw socket: {e}"),
+        };
+        let (_, closer_rx) = channel();
+        let mut hops = Vec::new();
+        traceroute(&tracer, 30, &closer_rx, |ttl, hop| {
+            hops.push((ttl, matches!(hop, Hop::Target { open: true, .. })))
+        })
+        .unwrap();
+        assert_eq!(hops, [(1, true)]);
+    }
+
+    /// Raw sockets need CAP_NET_RAW: without it the test is skipped.
+    #[test]
+    fn test_traceroute_closed_port() {
+        let target = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let tracer = match Traceroute::new(target, Duration::from_secs(1), Bind::default()) {
+            Ok(tracer) => tracer,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(e) => panic!("cannot open the raw socket: {e}"),
+        };
+        match tracer.probe(1).unwrap() {
+            Hop::Target { open: false, .. } => {}
+            other => panic!("expected a closed port, got {other:?}"),
+        }
+    }
+}
@@ -1,23 +1,85 @@
-use std::net::IpAddr;
-
-use chrono::{DateTime, Duration, Utc};
-
-use crate::user_input::UserInput;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    pinger::{PingError, Response, TcpInfo},
+    user_input::UserInput,
+};
+
+/// When Linux sends a lost SYN again, counted from the first one: the
+/// retransmission timeout starts at 1 second, or 3 seconds on older kernels,
+/// and doubles each time.
+const SYN_RETRANSMITS: [Duration; 9] = [
+    Duration::from_secs(1),
+    Duration::from_secs(3),
+    Duration::from_secs(7),
+    Duration::from_secs(9),
+    Duration::from_secs(15),
+    Duration::from_secs(21),
+    Duration::from_secs(31),
+    Duration::from_secs(45),
+    Duration::from_secs(63),
+];
+
+/// How much later than a SYN retransmit a connection may complete, on top of
+/// the usual rtt, to be attributed to it.
+const SYN_RETRANSMIT_SLACK: Duration = Duration::from_millis(200);
 
 pub struct Probe {
+    pub port: u16,
+    pub source_port: Option<u16>,
+    pub banner: Option<Response>,
+    pub response: Option<Response>,
+    /// How the server identified itself, when the protocol tells it.
+    pub server: Option<String>,
+    /// The time taken to connect to the proxy, when the probe goes through one.
+    pub proxy_time: Option<Duration>,
+    /// UDP replies received again for a probe already answered.
+    pub duplicate_replies: u32,
+    /// UDP replies received for earlier probes, after they timed out.
+    pub out_of_order_replies: u32,
+    /// The TCP_INFO right after connecting, when read.
+    pub tcp_info: Option<TcpInfo>,
+    /// The TCP_INFO after the exchange, when read and anything was exchanged.
+    pub exchange_tcp_info: Option<TcpInfo>,
+    /// When the probe was sent, by the wall clock.
     pub start: DateTime<Utc>,
+    /// The rtt of the probe, measured with the monotonic clock.
     pub elapsed: Duration,
-    pub err: Option<std::io::Error>,
+    pub err: Option<PingError>,
+    /// The time from the start of the cycle of the probe to the next one, by
+    /// the monotonic clock.
     pub cycle_duration: Duration,
+    /// The ticks of the schedule skipped after the cycle, which overran them.
+    pub skipped_ticks: u64,
 }
 
 pub struct Info {
     pub user_input: UserInput,
+    pub port: u16,
     pub succ_probes_streak: u128,
     pub fail_probes_streak: u128,
     pub succ_probes_counter: u128,
     pub fail_probes_counter: u128,
-    pub ip_addr: IpAddr,
+    /// Failed probes whose connection was dropped by the server while held open.
+    pub early_close_counter: u128,
+    /// Failed probes whose connection was established but whose server did
+    /// not speak the protocol as expected.
+    pub protocol_fail_counter: u128,
+    /// Failed UDP probes answered by an ICMP port unreachable.
+    pub unreachable_counter: u128,
+    /// Successful probes whose connection was slowed by a retransmitted SYN.
+    /// Unless asked otherwise, they still count in the rtt statistics.
+    pub slow_connect_counter: u128,
+    /// Whether the last probe was a slow connect.
+    pub last_slow_connect: bool,
+    pub duplicate_replies: u128,
+    pub out_of_order_replies: u128,
+    /// The last identification sent by the server.
+    pub server: Option<String>,
+    /// The target as shown to the user, with its address when it is known.
+    pub target: String,
     pub last_succ_probe: Option<DateTime<Utc>>,
     pub last_fail_probe: Option<DateTime<Utc>>,
     pub total_uptime: Duration,
@@ -25,66 +87,180 @@ pub struct Info {
     pub min_rtt: Duration,
     pub max_rtt: Duration,
     pub sum_rtt: Duration,
+    /// Successful probes whose server answered, like the protocol handshakes.
+    pub response_counter: u128,
+    pub min_response_time: Duration,
+    pub max_response_time: Duration,
+    pub sum_response_time: Duration,
+    /// The socket options of the probes, with their value or why they were
+    /// refused.
+    pub socket_options: Vec<String>,
+    /// The ticks of the schedule skipped because the probes overran them.
+    pub skipped_ticks: u128,
+    /// Probes whose TCP_INFO was read.
+    pub tcp_info_counter: u128,
+    /// SYNs sent again, as the TCP_INFO read right after connecting tells.
+    pub syn_retransmits: u128,
+    /// Segments sent again over the whole connections, SYNs included.
+    pub retransmits: u128,
+    /// The smoothed RTT of the kernel, at the end of the exchange.
+    pub min_kernel_rtt: Duration,
+    pub max_kernel_rtt: Duration,
+    pub sum_kernel_rtt: Duration,
     pub start_time: Option<DateTime<Utc>>,
     pub end_time: Option<DateTime<Utc>>,
 }
 
 impl Info {
-    pub fn new(user_input: UserInput, ip_addr: IpAddr) -> Info {
+    pub fn new(user_input: UserInput, target: String, port: u16) -> Info {
         Info {
             user_input,
-            ip_addr,
+            target,
+            port,
             succ_probes_streak: 0,
             fail_probes_streak: 0,
             succ_probes_counter: 0,
             fail_probes_counter: 0,
+            early_close_counter: 0,
+            protocol_fail_counter: 0,
+            unreachable_counter: 0,
+            slow_connect_counter: 0,
+            last_slow_connect: false,
+            duplicate_replies: 0,
+            out_of_order_replies: 0,
+            server: None,
             last_succ_probe: None,
             last_fail_probe: None,
-            total_uptime: Duration::zero(),
-            total_downtime: Duration::zero(),
-            min_rtt: Duration::max_value(),
-            max_rtt: Duration::min_value(),
-            sum_rtt: Duration::zero(),
+            total_uptime: Duration::ZERO,
+            total_downtime: Duration::ZERO,
+            min_rtt: Duration::MAX,
+            max_rtt: Duration::ZERO,
+            sum_rtt: Duration::ZERO,
+            response_counter: 0,
+            min_response_time: Duration::MAX,
+            max_response_time: Duration::ZERO,
+            sum_response_time: Duration::ZERO,
+            socket_options: Vec::new(),
+            skipped_ticks: 0,
+            tcp_info_counter: 0,
+            syn_retransmits: 0,
+            retransmits: 0,
+            min_kernel_rtt: Duration::MAX,
+            max_kernel_rtt: Duration::ZERO,
+            sum_kernel_rtt: Duration::ZERO,
             start_time: None,
             end_time: None,
         }
     }
 
     pub fn track(&mut self, probe: &Probe) {
+        self.last_slow_connect = probe.err.is_none() && self.is_slow_connect(probe);
         if probe.err.is_none() {
             self.succ_probes_streak += 1;
             self.fail_probes_streak = 0;
             self.succ_probes_counter += 1;
             self.last_succ_probe = Some(probe.start);
-            self.total_uptime = self.total_uptime + probe.cycle_duration;
-            self.min_rtt = Duration::min(self.min_rtt, probe.elapsed);
-            self.max_rtt = Duration::max(self.max_rtt, probe.elapsed);
-            self.sum_rtt = self.sum_rtt + probe.elapsed;
+            self.total_uptime += probe.cycle_duration;
+            if self.last_slow_connect {
+                self.slow_connect_counter += 1;
+            }
+            if !(self.last_slow_connect && self.user_input.exclude_slow_connects) {
+                self.min_rtt = self.min_rtt.min(probe.elapsed);
+                self.max_rtt = self.max_rtt.max(probe.elapsed);
+                self.sum_rtt += probe.elapsed;
+            }
+            if let Some(response) = &probe.response {
+                self.response_counter += 1;
+                self.min_response_time = self.min_response_time.min(response.time);
+                self.max_response_time = self.max_response_time.max(response.time);
+                self.sum_response_time += response.time;
+            }
         } else {
             self.succ_probes_streak = 0;
             self.fail_probes_streak += 1;
             self.fail_probes_counter += 1;
+            if let Some(PingError::EarlyClose { .. }) = probe.err {
+                self.early_close_counter += 1;
+            }
+            if probe.err.as_ref().is_some_and(PingError::is_protocol) {
+                self.protocol_fail_counter += 1;
+            }
+            if let Some(PingError::PortUnreachable(_)) = probe.err {
+                self.unreachable_counter += 1;
+            }
             self.last_fail_probe = Some(probe.start);
-            self.total_downtime = self.total_downtime + probe.cycle_duration;
+            self.total_downtime += probe.cycle_duration;
+        }
+        self.duplicate_replies += probe.duplicate_replies as u128;
+        self.out_of_order_replies += probe.out_of_order_replies as u128;
+        self.skipped_ticks += probe.skipped_ticks as u128;
+        if let Some(tcp_info) = &probe.tcp_info {
+            let last = probe.exchange_tcp_info.as_ref().unwrap_or(tcp_info);
+            self.tcp_info_counter += 1;
+            self.syn_retransmits += tcp_info.retransmits as u128;
+            self.retransmits += last.retransmits as u128;
+            self.min_kernel_rtt = self.min_kernel_rtt.min(last.rtt);
+            self.max_kernel_rtt = self.max_kernel_rtt.max(last.rtt);
+            self.sum_kernel_rtt += last.rtt;
+        }
+        if probe.server.is_some() {
+            self.server.clone_from(&probe.server);
         }
         if self.start_time.is_none() {
             self.start_time = Some(probe.start)
         }
-        self.end_time = Some(probe.start + probe.elapsed);
+        self.end_time =
+            Some(probe.start + chrono::Duration::from_std(probe.elapsed).unwrap_or_default());
+    }
+
+    /// Whether the connection of the probe was slowed by a retransmitted SYN,
+    /// as its TCP_INFO tells or, without it, as its rtt exceeds one of the
+    /// SYN retransmits by no more than the usual rtt. The rtt alone cannot
+    /// tell apart a link whose usual rtt nears the first retransmit.
+    fn is_slow_connect(&self, probe: &Probe) -> bool {
+        if self.user_input.udp || self.user_input.syn || self.user_input.unix {
+            return false;
+        }
+        if let Some(tcp_info) = &probe.tcp_info {
+            return tcp_info.retransmits > 0;
+        }
+        let usual_rtt = self.min_rtt;
+        if usual_rtt >= SYN_RETRANSMITS[0] / 2 {
+            return false;
+        }
+        SYN_RETRANSMITS
+            .iter()
+            .rev()
+            .find(|retransmit| **retransmit <= probe.elapsed)
+            .is_some_and(|retransmit| {
+                probe.elapsed - *retransmit <= usual_rtt + SYN_RETRANSMIT_SLACK
+            })
+    }
+
+    /// The successful probes counted in the rtt statistics.
+    pub fn rtt_counter(&self) -> u128 {
+        match self.user_input.exclude_slow_connects {
+            true => self.succ_probes_counter - self.slow_connect_counter,
+            false => self.succ_probes_counter,
+        }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use std::{
         io::{self, Error},
         net::{AddrParseError, IpAddr},
         str::FromStr,
     };
 
-    use chrono::{DateTime, Duration, Utc};
+    use std::time::Duration;
+
+    use chrono::{DateTime, Utc};
 
     use crate::{
+        pinger::{PingError, Response, TcpInfo},
+        target::Protocol,
         tracker::{Info, Probe},
         user_input::UserInput,
     };
@@ -97,10 +273,21 @@ mod tests {
         pub fn new() -> ProbeBuilder {
             ProbeBuilder {
                 probe: Probe {
+                    port: 443,
+                    source_port: None,
+                    banner: None,
+                    response: None,
+                    server: None,
+                    proxy_time: None,
+                    duplicate_replies: 0,
+                    out_of_order_replies: 0,
+                    tcp_info: None,
+                    exchange_tcp_info: None,
                     start: Utc::now(),
-                    elapsed: Duration::seconds(1),
+                    elapsed: Duration::from_secs(1),
                     err: None,
-                    cycle_duration: Duration::seconds(1),
+                    cycle_duration: Duration::from_secs(1),
+                    skipped_ticks: 0,
                 },
             }
         }
@@ -115,8 +302,8 @@ mod tests {
             self
         }
 
-        pub fn err(mut self, err: std::io::Error) -> ProbeBuilder {
-            self.probe.err = Some(err);
+        pub fn err(mut self, err: impl Into<PingError>) -> ProbeBuilder {
+            self.probe.err = Some(err.into());
             self
         }
 
@@ -190,12 +377,15 @@ mod tests {
         let mut info = Info::new(
             UserInput {
                 url: "example.com".to_owned(),
-                port: 443,
-                timeout: Some(Duration::seconds(1)),
+                ports: vec![443],
+                protocol: Protocol::Tcp,
+                timeout: Some(chrono::Duration::seconds(1)),
                 probes_count: None,
-                interval_between_probes: Duration::seconds(1),
+                interval_between_probes: chrono::Duration::seconds(1),
+                ..Default::default()
             },
-            IpAddr::from_str("93.184.216.34")?,
+            format!("example.com ({})", IpAddr::from_str("93.184.216.34")?),
+            443,
         );
         probes.iter().for_each(|probe| info.track(probe));
         Ok(info)
@@ -205,6 +395,166 @@ mod tests {
         create_info_from_probes(&[]).unwrap()
     }
 
+    #[test]
+    fn test_early_close_counter() -> Result<(), AddrParseError> {
+        let early_close = || {
+            ProbeBuilder::new()
+                .err(PingError::EarlyClose {
+                    after: Duration::from_millis(20),
+                    err: None,
+                })
+                .build()
+        };
+        let probes = [early_close(), failure(), success(), early_close()];
+        let info = create_info_from_probes(&probes)?;
+        assert_eq!(info.fail_probes_counter, 3);
+        assert_eq!(info.early_close_counter, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_protocol_fail_counter() -> Result<(), AddrParseError> {
+        let protocol_failure = || {
+            ProbeBuilder::new()
+                .err(PingError::protocol("smtp", "replied 421 busy"))
+                .build()
+        };
+        let probes = [protocol_failure(), failure(), success(), protocol_failure()];
+        let info = create_info_from_probes(&probes)?;
+        assert_eq!(info.fail_probes_counter, 3);
+        assert_eq!(info.protocol_fail_counter, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_server() -> Result<(), AddrParseError> {
+        let identified = |server: &str| {
+            let mut probe = success();
+            probe.server = Some(server.to_owned());
+            probe
+        };
+        let probes = [
+            identified("OpenSSH_9.5"),
+            identified("OpenSSH_9.6"),
+            failure(),
+        ];
+        let info = create_info_from_probes(&probes)?;
+        assert_eq!(info.server.as_deref(), Some("OpenSSH_9.6"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_time() -> Result<(), AddrParseError> {
+        let answered = |ms: u64| {
+            let mut probe = success();
+            probe.response = Some(Response {
+                data: b"HTTP/1.1 101 Switching Protocols".to_vec(),
+                time: Duration::from_millis(ms),
+            });
+            probe
+        };
+        let probes = [answered(4), success(), answered(10), failure()];
+        let info = create_info_from_probes(&probes)?;
+        assert_eq!(info.response_counter, 2);
+        assert_eq!(info.min_response_time.as_millis(), 4);
+        assert_eq!(info.max_response_time.as_millis(), 10);
+        assert_eq!(info.sum_response_time.as_millis(), 14);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tcp_info() -> Result<(), AddrParseError> {
+        let tcp_info = |rtt_ms: u64, retransmits: u32| TcpInfo {
+            rtt: Duration::from_millis(rtt_ms),
+            retransmits,
+            ..Default::default()
+        };
+        let mut syn_retransmitted = success();
+        syn_retransmitted.tcp_info = Some(tcp_info(3, 1));
+        let mut exchanged = success();
+        exchanged.tcp_info = Some(tcp_info(2, 0));
+        exchanged.exchange_tcp_info = Some(tcp_info(5, 2));
+        let probes = [syn_retransmitted, success(), exchanged];
+        let info = create_info_from_probes(&probes)?;
+        assert_eq!(info.tcp_info_counter, 2);
+        assert_eq!(info.syn_retransmits, 1);
+        assert_eq!(info.retransmits, 3);
+        assert_eq!(info.min_kernel_rtt.as_millis(), 3);
+        assert_eq!(info.max_kernel_rtt.as_millis(), 5);
+        assert_eq!(info.sum_kernel_rtt.as_millis(), 8);
+        Ok(())
+    }
+
+    fn connect(ms: u64) -> Probe {
+        ProbeBuilder::new()
+            .elapsed(Duration::from_millis(ms))
+            .build()
+    }
+
+    #[test]
+    fn test_slow_connect() -> Result<(), AddrParseError> {
+        // a SYN lost once, twice and once with the RTO of older kernels,
+        // then a probe just slow
+        let probes = [
+            connect(40),
+            connect(1050),
+            connect(3020),
+            connect(3100),
+            connect(1600),
+        ];
+        let info = create_info_from_probes(&probes)?;
+        assert_eq!(info.slow_connect_counter, 3);
+        assert_eq!(info.min_rtt, Duration::from_millis(40));
+        assert_eq!(info.max_rtt, Duration::from_millis(3100));
+        assert_eq!(info.rtt_counter(), 5);
+        assert!(!info.last_slow_connect);
+        // TCP_INFO tells better than the rtt
+        let with_tcp_info = |ms, retransmits| {
+            let mut probe = connect(ms);
+            probe.tcp_info = Some(TcpInfo {
+                retransmits,
+                ..Default::default()
+            });
+            probe
+        };
+        let info = create_info_from_probes(&[with_tcp_info(40, 1), with_tcp_info(1050, 0)])?;
+        assert_eq!(info.slow_connect_counter, 1);
+        assert!(!info.last_slow_connect);
+        // a link this slow, or one not measured yet, is not told apart
+        let info = create_info_from_probes(&[connect(1050), connect(950), connect(1100)])?;
+        assert_eq!(info.slow_connect_counter, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_slow_connect_excluded() {
+        let mut info = Info::new(
+            UserInput {
+                exclude_slow_connects: true,
+                ..Default::default()
+            },
+            "localhost (127.0.0.1)".to_owned(),
+            443,
+        );
+        for probe in [connect(20), connect(1030), connect(30)] {
+            info.track(&probe);
+        }
+        assert_eq!(info.slow_connect_counter, 1);
+        assert_eq!(info.max_rtt, Duration::from_millis(30));
+        assert_eq!(info.sum_rtt, Duration::from_millis(50));
+        assert_eq!(info.rtt_counter(), 2);
+        let mut udp = Info::new(
+            UserInput {
+                udp: true,
+                ..Default::default()
+            },
+            "localhost (127.0.0.1)".to_owned(),
+            443,
+        );
+        udp.track(&connect(1030));
+        assert_eq!(udp.slow_connect_counter, 0);
+    }
+
     #[test]
     fn test_last_succ_and_fail_single_succ() -> Result<(), AddrParseError> {
         let probes = [success()];
@@ -226,7 +576,7 @@ mod tests {
     #[test]
     fn test_last_succ_and_fail_mult_succ() -> Result<(), AddrParseError> {
         let time = Utc::now();
-        let second = Duration::seconds(1);
+        let second = Duration::from_secs(1);
         let probes = [
             ProbeBuilder::new().start(time + second).build(),
             ProbeBuilder::new().start(time + second * 2).build(),
@@ -241,7 +591,7 @@ mod tests {
     #[test]
     fn test_last_succ_and_fail_mult_fail() -> Result<(), AddrParseError> {
         let time = Utc::now();
-        let second = Duration::seconds(1);
+        let second = Duration::from_secs(1);
         let probes = [
             ProbeBuilder::new()
                 .start(time + second)
@@ -265,7 +615,7 @@ mod tests {
     #[test]
     fn test_last_succ_and_fail_mixed() -> Result<(), AddrParseError> {
         let time = Utc::now();
-        let second = Duration::seconds(1);
+        let second = Duration::from_secs(1);
         let probes = [
             ProbeBuilder::new()
                 .start(time + second)
@@ -292,51 +642,51 @@ mod tests {
     fn test_total_uptime_downtime() -> Result<(), AddrParseError> {
         let probes = [
             ProbeBuilder::new()
-                .cycle_duration(Duration::seconds(2))
+                .cycle_duration(Duration::from_secs(2))
                 .build(),
             ProbeBuilder::new()
-                .cycle_duration(Duration::seconds(3))
+                .cycle_duration(Duration::from_secs(3))
                 .build(),
             ProbeBuilder::new()
-                .cycle_duration(Duration::seconds(2))
+                .cycle_duration(Duration::from_secs(2))
                 .err(dummy_error())
                 .build(),
             ProbeBuilder::new()
-                .cycle_duration(Duration::seconds(5))
+                .cycle_duration(Duration::from_secs(5))
                 .build(),
             ProbeBuilder::new()
-                .cycle_duration(Duration::seconds(20))
+                .cycle_duration(Duration::from_secs(20))
                 .err(dummy_error())
                 .build(),
         ];
         let info = create_info_from_probes(&probes)?;
-        assert_eq!(info.total_uptime, Duration::seconds(10));
-        assert_eq!(info.total_downtime, Duration::seconds(22));
+        assert_eq!(info.total_uptime, Duration::from_secs(10));
+        assert_eq!(info.total_downtime, Duration::from_secs(22));
         Ok(())
     }
 
     #[test]
     fn test_min_max_sum() -> Result<(), AddrParseError> {
         let probes = [
-            ProbeBuilder::new().elapsed(Duration::seconds(3)).build(),
-            ProbeBuilder::new().elapsed(Duration::seconds(1)).build(),
+            ProbeBuilder::new().elapsed(Duration::from_secs(3)).build(),
+            ProbeBuilder::new().elapsed(Duration::from_secs(1)).build(),
             ProbeBuilder::new()
-                .elapsed(Duration::seconds(20))
+                .elapsed(Duration::from_secs(20))
                 .err(dummy_error())
                 .build(),
-            ProbeBuilder::new().elapsed(Duration::seconds(2)).build(),
+            ProbeBuilder::new().elapsed(Duration::from_secs(2)).build(),
         ];
         let info = create_info_from_probes(&probes)?;
-        assert_eq!(info.min_rtt, Duration::seconds(1));
-        assert_eq!(info.max_rtt, Duration::seconds(3));
-        assert_eq!(info.sum_rtt, Duration::seconds(6));
+        assert_eq!(info.min_rtt, Duration::from_secs(1));
+        assert_eq!(info.max_rtt, Duration::from_secs(3));
+        assert_eq!(info.sum_rtt, Duration::from_secs(6));
         Ok(())
     }
 
     #[test]
     fn test_start_end_time() -> Result<(), AddrParseError> {
         let time = Utc::now();
-        let second = Duration::seconds(1);
+        let second = Duration::from_secs(1);
         let probes = [
             ProbeBuilder::new().start(time + second).build(),
             ProbeBuilder::new().start(time + second * 2).build(),
@@ -344,7 +694,7 @@ mod tests {
             ProbeBuilder::new()
                 .start(time + second * 4)
                 .start(Utc::now())
-                .elapsed(Duration::seconds(3))
+                .elapsed(Duration::from_secs(3))
                 .build(),
         ];
         let info = create_info_from_probes(&probes)?;
@@ -1,42 +1,378 @@
 use chrono::Duration;
-use clap::{arg, value_parser, Arg, Command};
-use std::ffi::OsString;
+use clap::{
+    arg, error::ErrorKind, parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command,
+};
+use regex::bytes::Regex;
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+};
 
+use crate::{
+    pinger::{
+        encode_name, parse_record_type, Close, MqttVersion, Proxy, ProxyHeader, ProxyProtocol,
+        SocketOptions,
+    },
+    target::{Protocol, Target},
+};
+
+#[derive(Clone, Debug)]
 pub struct UserInput {
+    /// The host, or the path of the socket when `unix` is set.
     pub url: String,
-    pub port: u16,
+    pub unix: bool,
+    /// The ports probed, none for a Unix socket.
+    pub ports: Vec<u16>,
+    pub protocol: Protocol,
     pub timeout: Option<Duration>,
     pub probes_count: Option<u128>,
     pub interval_between_probes: Duration,
+    /// The fraction of the interval the probes are randomly spread over.
+    pub interval_jitter: f64,
+    /// Whether the probes start on multiples of the interval by the wall clock.
+    pub interval_align: bool,
+    pub source: Option<IpAddr>,
+    pub interface: Option<String>,
+    pub source_ports: Option<RangeInclusive<u16>>,
+    pub hold: Option<Duration>,
+    pub close: Close,
+    pub syn: bool,
+    /// Whether the probes are UDP datagrams instead of TCP connections.
+    pub udp: bool,
+    /// The TTL, or hop limit on IPv6, of the probes.
+    pub ttl: Option<u32>,
+    pub socket_options: SocketOptions,
+    /// Whether the kernel TCP_INFO of the probe connections is read.
+    pub tcp_info: bool,
+    /// Whether the connections slowed by a retransmitted SYN are left out of
+    /// the rtt statistics.
+    pub exclude_slow_connects: bool,
+    /// The maximum TTL of the traceroute, when one is requested instead of probing.
+    pub traceroute: Option<u32>,
+    /// The most bytes of banner read after connecting, if the banner is read.
+    pub read_banner: Option<usize>,
+    pub expect_banner: Option<Regex>,
+    /// The data sent to the server after connecting.
+    pub send: Option<Vec<u8>>,
+    /// What the answer to `send` should match.
+    pub expect: Option<Regex>,
+    /// The name queried by the DNS probes.
+    pub dns_name: String,
+    /// The record type queried by the DNS probes.
+    pub dns_type: u16,
+    /// The name the SMTP probes send with EHLO, if they go past the greeting.
+    pub ehlo: Option<String>,
+    /// The path of the WebSocket upgrade request.
+    pub ws_path: String,
+    /// The headers added to the WebSocket upgrade request.
+    pub ws_headers: Vec<(String, String)>,
+    /// Whether the WebSocket probes send a ping and wait for the pong.
+    pub ws_ping: bool,
+    /// The service named in the gRPC health checks, the whole server if empty.
+    pub grpc_service: String,
+    /// Whether the protocol is spoken over TLS.
+    pub tls: bool,
+    /// Whether the certificate of the server is checked, when over TLS.
+    pub tls_verify: bool,
+    pub mqtt_version: MqttVersion,
+    /// The client id of the MQTT probes, one made of the process id if `None`.
+    pub mqtt_client_id: Option<String>,
+    pub mqtt_username: Option<String>,
+    /// The password of the MQTT probes, read from the environment.
+    pub mqtt_password: Option<String>,
+    /// The proxy the probes go through, if any.
+    pub proxy: Option<Proxy>,
+    /// The PROXY protocol header sent after connecting, if any.
+    pub proxy_protocol: Option<ProxyHeader>,
 }
 
-impl Clone for UserInput {
-    fn clone(&self) -> Self {
+impl Default for UserInput {
+    fn default() -> Self {
         Self {
-            url: self.url.clone(),
-            port: self.port,
-            timeout: self.timeout,
-            probes_count: self.probes_count,
-            interval_between_probes: self.interval_between_probes,
+            url: String::new(),
+            unix: false,
+            ports: vec![443],
+            protocol: Protocol::Tcp,
+            timeout: Some(Duration::seconds(1)),
+            probes_count: None,
+            interval_between_probes: Duration::seconds(1),
+            interval_jitter: 0.0,
+            interval_align: false,
+            source: None,
+            interface: None,
+            source_ports: None,
+            hold: None,
+            close: Close::Drop,
+            syn: false,
+            udp: false,
+            ttl: None,
+            socket_options: SocketOptions::default(),
+            tcp_info: false,
+            exclude_slow_connects: false,
+            traceroute: None,
+            read_banner: None,
+            expect_banner: None,
+            send: None,
+            expect: None,
+            dns_name: ".".to_owned(),
+            dns_type: 2,
+            ehlo: None,
+            ws_path: "/".to_owned(),
+            ws_headers: Vec::new(),
+            ws_ping: false,
+            grpc_service: String::new(),
+            tls: false,
+            tls_verify: true,
+            mqtt_version: MqttVersion::V311,
+            mqtt_client_id: None,
+            mqtt_username: None,
+            mqtt_password: None,
+            proxy: None,
+            proxy_protocol: None,
+        }
+    }
+}
+
+fn parse_timeout(value: &str) -> Result<f32, String> {
+    let timeout = value.parse::<f32>().map_err(|e| e.to_string())?;
+    if timeout < 0.0 || !timeout.is_finite() {
+        return Err("timeout should be a positive number".to_owned());
+    }
+    Ok(timeout)
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent = value.parse::<f64>().map_err(|e| e.to_string())?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err("percentage should be between 0 and 100".to_owned());
+    }
+    Ok(percent)
+}
+
+/// Parses a byte in decimal or in hexadecimal with a `0x` prefix.
+fn parse_byte(value: &str) -> Result<u32, String> {
+    let byte = match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse::<u8>(),
+    };
+    byte.map(u32::from)
+        .map_err(|_| format!("'{value}' is not a byte"))
+}
+
+/// Parses a port, 0 being left to the system and never a valid choice.
+fn parse_port(port: &str) -> Result<u16, String> {
+    match port.trim().parse::<u16>() {
+        Ok(0) | Err(_) => Err(format!("'{port}' is not a valid port")),
+        Ok(port) => Ok(port),
+    }
+}
+
+/// Parses a comma separated list of ports and port ranges like
+/// `80,443,8000-8010`, dropping the duplicates.
+fn parse_ports(value: &str) -> Result<Vec<u16>, String> {
+    let mut ports = Vec::new();
+    let mut seen = HashSet::new();
+    for item in value.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (parse_port(first)?, parse_port(last)?),
+            None => (parse_port(item)?, parse_port(item)?),
+        };
+        if first > last {
+            return Err(format!("'{item}' is an empty port range"));
+        }
+        ports.extend((first..=last).filter(|port| seen.insert(*port)));
+    }
+    Ok(ports)
+}
+
+fn parse_port_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    let (first, last) = value
+        .split_once('-')
+        .ok_or_else(|| "expected a range like 40000-40010".to_owned())?;
+    let range = parse_port(first)?..=parse_port(last)?;
+    if range.is_empty() {
+        return Err(format!("'{value}' is an empty port range"));
+    }
+    Ok(range)
+}
+
+/// Parses a duration like `500ms`, `2s`, `1.5m` or `3`, which is in seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit_ms) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1000.0)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60_000.0)
+    } else {
+        (value, 1000.0)
+    };
+    let number = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("'{value}' is not a duration like 500ms, 2s or 1m"))?;
+    if number < 0.0 || !number.is_finite() {
+        return Err("duration should be a positive number".to_owned());
+    }
+    Ok(Duration::microseconds((number * unit_ms * 1000.0) as i64))
+}
+
+fn parse_close(value: &str) -> Result<Close, String> {
+    match value {
+        "drop" => Ok(Close::Drop),
+        "fin" => Ok(Close::Graceful),
+        "rst" => Ok(Close::Reset),
+        _ => Err(format!("'{value}' is not one of drop, fin, rst")),
+    }
+}
+
+fn parse_mqtt_version(value: &str) -> Result<MqttVersion, String> {
+    match value {
+        "3.1.1" => Ok(MqttVersion::V311),
+        "5" => Ok(MqttVersion::V5),
+        _ => Err(format!("'{value}' is not one of 3.1.1, 5")),
+    }
+}
+
+/// Reads the variable `name` of the environment, so that secrets are not
+/// given on the command line.
+fn parse_env(name: &str) -> Result<String, String> {
+    std::env::var(name).map_err(|e| format!("cannot read '{name}': {e}"))
+}
+
+fn parse_proxy_protocol(value: &str) -> Result<ProxyProtocol, String> {
+    match value {
+        "v1" => Ok(ProxyProtocol::V1),
+        "v2" => Ok(ProxyProtocol::V2),
+        _ => Err(format!("'{value}' is not one of v1, v2")),
+    }
+}
+
+/// Parses a header like `Origin: https://example.com`.
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_owned(), value.trim().to_owned()))
+        }
+        _ => Err(format!("'{value}' is not a header like 'Name: value'")),
+    }
+}
+
+/// Parses an address with an optional port, like `192.0.2.1`,
+/// `192.0.2.1:4000` or `[2001:db8::1]:4000`.
+fn parse_address(value: &str) -> Result<(IpAddr, Option<u16>), String> {
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Ok((ip, None));
+    }
+    value
+        .parse::<SocketAddr>()
+        .map(|socket| (socket.ip(), Some(socket.port())))
+        .map_err(|_| format!("'{value}' is not an address like 192.0.2.1 or 192.0.2.1:4000"))
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = value.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("hex data should have an even number of digits".to_owned());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| format!("'{pair}' is not an hex byte"))
+        })
+        .collect()
+}
+
+/// Replaces the escapes `\r`, `\n`, `\t`, `\0`, `\\` and `\xHH` with the bytes they stand for.
+fn unescape(value: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            data.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => data.push(b'\r'),
+            Some('n') => data.push(b'\n'),
+            Some('t') => data.push(b'\t'),
+            Some('0') => data.push(0),
+            Some('\\') => data.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                data.extend(parse_hex(&hex)?);
+            }
+            other => return Err(format!("unknown escape '\\{}'", other.unwrap_or(' '))),
         }
     }
+    Ok(data)
+}
+
+/// Parses the data to send: `hex:` followed by hex digits, `@` followed by
+/// the path of a file, or a string which may contain escapes like `\r\n`.
+fn parse_payload(value: &str) -> Result<Vec<u8>, String> {
+    if let Some(hex) = value.strip_prefix("hex:") {
+        parse_hex(hex)
+    } else if let Some(path) = value.strip_prefix('@') {
+        std::fs::read(path).map_err(|e| format!("cannot read '{path}': {e}"))
+    } else {
+        unescape(value)
+    }
+}
+
+/// Parses the expected data: `hex:` followed by the hex digits the data
+/// should start with, or a regex.
+fn parse_expect(value: &str) -> Result<Regex, String> {
+    let pattern = match value.strip_prefix("hex:") {
+        Some(hex) => parse_hex(hex)?
+            .iter()
+            .fold("(?s-u)^".to_owned(), |pattern, byte| {
+                pattern + &format!("\\x{byte:02x}")
+            }),
+        None => value.to_owned(),
+    };
+    Regex::new(&pattern).map_err(|e| e.to_string())
+}
+
+fn required<T: Clone + Send + Sync + 'static>(
+    matches: &ArgMatches,
+    id: &str,
+) -> Result<T, clap::Error> {
+    matches.get_one::<T>(id).cloned().ok_or_else(|| {
+        clap::Error::raw(
+            ErrorKind::MissingRequiredArgument,
+            format!("the argument '{id}' is required\n"),
+        )
+    })
 }
 
-pub fn parse<I, T>(args: I) -> UserInput
+pub fn parse<I, T>(args: I) -> Result<UserInput, clap::Error>
 where
     I: IntoIterator<Item = T>,
     T: Into<OsString> + Clone,
 {
-    let matches = Command::new("test")
-        .arg(Arg::new("url"))
+    let mut command = Command::new("rustcping")
+        .arg(
+            Arg::new("url")
+                .required(true)
+                .value_parser(|s: &str| s.parse::<Target>()),
+        )
         .arg(
             arg!(--port <VALUE>)
-                .value_parser(value_parser!(u16))
+                .value_parser(parse_ports)
                 .default_value("443"),
         )
+        .arg(
+            arg!(--protocol <NAME> "Checks the server speaks tcp, redis, postgres, mysql, dns, smtp, ssh, ftp, websocket, grpc or mqtt")
+                .value_parser(|s: &str| s.parse::<Protocol>()),
+        )
         .arg(
             arg!(--timeout <VALUE>)
-                .value_parser(value_parser!(f32))
+                .value_parser(parse_timeout)
                 .allow_hyphen_values(true)
                 .default_value("1.0"),
         )
@@ -45,26 +381,354 @@ where
                 .value_parser(value_parser!(u128))
                 .default_value("0"),
         )
-        .get_matches_from(args);
-    let url = matches
-        .get_one::<String>("url")
-        .expect("required")
-        .to_owned();
-    let port = matches.get_one::<u16>("port").expect("required").to_owned();
-    let timeout = matches
-        .get_one::<f32>("timeout")
-        .expect("required")
-        .to_owned();
-    if timeout < 0.0 {
-        panic!("Timeout should be a positive number")
-    }
-    let count = matches
-        .get_one::<u128>("count")
-        .expect("required")
-        .to_owned();
-    UserInput {
-        url,
-        port,
+        .arg(
+            arg!(--"interval-jitter" <PERCENT> "Spreads the probes randomly over this percentage of the interval")
+                .value_parser(parse_percent)
+                .allow_hyphen_values(true)
+                .default_value("0"),
+        )
+        .arg(arg!(--"interval-align" "Starts the probes on multiples of the interval by the wall clock, like every full second"))
+        .arg(
+            arg!(--source <ADDRESS> "Source address of the probes")
+                .value_parser(value_parser!(IpAddr)),
+        )
+        .arg(arg!(--interface <NAME> "Network interface the probes are sent from"))
+        .arg(
+            arg!(--"source-port" <PORT> "Source port of the probes")
+                .value_parser(parse_port)
+                .conflicts_with("source-port-range"),
+        )
+        .arg(
+            arg!(--"source-port-range" <RANGE> "Source ports used in turn by the probes")
+                .value_parser(parse_port_range),
+        )
+        .arg(
+            arg!(--hold <DURATION> "Keeps each connection open, failing if the server drops it")
+                .value_parser(parse_duration)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            arg!(--close <MODE> "How connections are closed: drop, fin or rst, the default with --source-port")
+                .value_parser(parse_close),
+        )
+        .arg(
+            arg!(--syn "Sends half-open probes from a raw socket, needs CAP_NET_RAW")
+                .conflicts_with_all(["hold", "close", "read-banner", "expect-banner", "send"]),
+        )
+        .arg(
+            arg!(--udp "Sends a datagram per probe and waits for the reply, {seq} in --send being replaced by the sequence number").conflicts_with_all([
+                "syn",
+                "source-port-range",
+                "hold",
+                "close",
+                "read-banner",
+                "expect-banner",
+            ]),
+        )
+        .arg(
+            arg!(--tfo "Sends the --send data in the SYN with TCP Fast Open, on Linux")
+                .requires("send")
+                // the SYN waits for the request, which is sent after these
+                .conflicts_with_all([
+                    "syn",
+                    "udp",
+                    "read-banner",
+                    "expect-banner",
+                    "proxy",
+                    "proxy-protocol",
+                ]),
+        )
+        .arg(
+            arg!(--nodelay "Disables the Nagle algorithm on the probe connections")
+                .conflicts_with_all(["syn", "udp"]),
+        )
+        .arg(
+            arg!(--mss <BYTES> "Maximum segment size announced by the probes")
+                .value_parser(value_parser!(u32).range(1..=65535))
+                .conflicts_with_all(["syn", "udp"]),
+        )
+        .arg(
+            arg!(--tos <VALUE> "TOS byte, or traffic class on IPv6, of the probes")
+                .value_parser(parse_byte)
+                .conflicts_with_all(["syn", "dscp"]),
+        )
+        .arg(
+            arg!(--dscp <VALUE> "DSCP of the probes, the upper 6 bits of the TOS byte")
+                .value_parser(value_parser!(u32).range(0..64))
+                .conflicts_with("syn"),
+        )
+        .arg(
+            arg!(--ttl <HOPS> "TTL, or hop limit on IPv6, of the probes")
+                .value_parser(value_parser!(u32).range(1..=255))
+                .conflicts_with("syn"),
+        )
+        .arg(
+            arg!(--congestion <ALGORITHM> "Congestion control of the probe connections, on Linux")
+                .conflicts_with_all(["syn", "udp"]),
+        )
+        .arg(
+            arg!(--"tcp-info" "Reads the kernel RTT and retransmits of the connections, on Linux")
+                .conflicts_with_all(["syn", "udp"]),
+        )
+        .arg(
+            arg!(--"exclude-slow-connects" "Leaves the connections slowed by a retransmitted SYN out of the rtt statistics, needs --tcp-info or a --timeout above 1s")
+                .conflicts_with_all(["syn", "udp"]),
+        )
+        .arg(
+            arg!(--traceroute "Traces the path to the target port, needs CAP_NET_RAW")
+                .conflicts_with_all([
+                    "syn",
+                    "udp",
+                    "ttl",
+                    "hold",
+                    "close",
+                    "count",
+                    "interval-jitter",
+                    "interval-align",
+                    "send",
+                    "expect",
+                    "read-banner",
+                    "expect-banner",
+                    "tcp-info",
+                    "exclude-slow-connects",
+                    "tfo",
+                ]),
+        )
+        .arg(
+            arg!(--"max-hops" <HOPS> "Maximum TTL of the traceroute")
+                .value_parser(value_parser!(u32).range(1..=255))
+                .requires("traceroute")
+                .default_value("30"),
+        )
+        .arg(
+            arg!(--"read-banner" [BYTES] "Reads the banner the server sends after connecting, 256 bytes or --read-banner=BYTES")
+                .value_parser(value_parser!(u16).range(1..))
+                .require_equals(true)
+                .default_missing_value("256"),
+        )
+        .arg(
+            arg!(--"expect-banner" <REGEX> "Fails the probes whose banner does not match")
+                .value_parser(|s: &str| Regex::new(s)),
+        )
+        .arg(
+            arg!(--send <DATA> "Sends a string, hex:<digits> or @<file> after connecting")
+                .value_parser(parse_payload),
+        )
+        .arg(
+            arg!(--expect <DATA> "Fails the probes whose answer does not match a regex or hex:<digits>")
+                .value_parser(parse_expect)
+                .requires("send"),
+        )
+        .arg(
+            arg!(--"dns-name" <NAME> "Name queried by the dns protocol")
+                .value_parser(|s: &str| encode_name(s).map(|_| s.to_owned()))
+                .default_value("."),
+        )
+        .arg(
+            arg!(--"dns-type" <TYPE> "Record type queried by the dns protocol")
+                .value_parser(parse_record_type)
+                .default_value("NS"),
+        )
+        .arg(
+            arg!(--ehlo [NAME] "Sends EHLO and QUIT after the greeting of the smtp protocol")
+                .default_missing_value("localhost"),
+        )
+        .arg(
+            arg!(--"ws-path" <PATH> "Path of the upgrade request of the websocket protocol, by default the one of the target URL")
+                .value_parser(|s: &str| match s.starts_with('/') {
+                    true => Ok(s.to_owned()),
+                    false => Err("the path should start with '/'"),
+                })
+                .default_value("/"),
+        )
+        .arg(
+            arg!(--"ws-header" <HEADER> "Header added to the upgrade request of the websocket protocol")
+                .value_parser(parse_header)
+                .action(ArgAction::Append),
+        )
+        .arg(arg!(--"ws-ping" "Sends a ping after the websocket upgrade, expecting a pong"))
+        .arg(
+            arg!(--"grpc-service" <NAME> "Service checked by the grpc protocol, the whole server by default")
+                .default_value(""),
+        )
+        .arg(arg!(--tls "Speaks the grpc protocol over TLS"))
+        .arg(
+            arg!(--"mqtt-version" <VERSION> "Version of the mqtt protocol: 3.1.1 or 5")
+                .value_parser(parse_mqtt_version)
+                .default_value("3.1.1"),
+        )
+        .arg(arg!(--"mqtt-client-id" <ID> "Client id of the mqtt protocol"))
+        .arg(arg!(--"mqtt-username" <NAME> "User name of the mqtt protocol"))
+        .arg(
+            arg!(--"mqtt-password-env" <VARIABLE> "Environment variable holding the password of the mqtt protocol")
+                .value_parser(parse_env)
+                .requires("mqtt-username"),
+        )
+        .arg(
+            arg!(--"tls-no-verify" "Accepts any certificate of the server")
+                .requires("tls"),
+        )
+        .arg(
+            arg!(--proxy <URL> "Probes through a socks5://host:port or http://host:port proxy")
+                .value_parser(|s: &str| s.parse::<Proxy>())
+                .conflicts_with_all(["syn", "udp", "traceroute"]),
+        )
+        .arg(
+            arg!(--"proxy-protocol" <VERSION> "Sends a PROXY protocol v1 or v2 header after connecting")
+                .value_parser(parse_proxy_protocol)
+                // through a proxy, the addresses of the connection to the
+                // target are not known
+                .conflicts_with_all(["syn", "udp", "traceroute", "proxy"]),
+        )
+        .arg(
+            arg!(--"proxy-protocol-source" <ADDRESS> "Client address claimed in the PROXY protocol header")
+                .value_parser(parse_address)
+                .requires("proxy-protocol"),
+        );
+    let matches = command.try_get_matches_from_mut(args)?;
+    let target = required::<Target>(&matches, "url")?;
+    let port_flag = required::<Vec<u16>>(&matches, "port")?;
+    if target.unix {
+        // only the connection is checked on Unix sockets
+        let tcp_only = [
+            "port",
+            "protocol",
+            "source",
+            "interface",
+            "source-port",
+            "source-port-range",
+            "hold",
+            "close",
+            "syn",
+            "udp",
+            "tfo",
+            "nodelay",
+            "mss",
+            "tos",
+            "dscp",
+            "ttl",
+            "congestion",
+            "tcp-info",
+            "exclude-slow-connects",
+            "traceroute",
+            "read-banner",
+            "expect-banner",
+            "send",
+            "proxy",
+            "proxy-protocol",
+        ];
+        if let Some(id) = tcp_only
+            .into_iter()
+            .find(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        {
+            return Err(command.error(
+                ErrorKind::ArgumentConflict,
+                format!("--{id} cannot be used with a Unix socket"),
+            ));
+        }
+    }
+    // these modes do not speak the protocols: they win over the protocol
+    // implied by the scheme, but not over one asked explicitly
+    let tcp_mode = [
+        "syn",
+        "udp",
+        "traceroute",
+        "read-banner",
+        "expect-banner",
+        "send",
+    ]
+    .into_iter()
+    .find(|id| matches.value_source(id) == Some(ValueSource::CommandLine));
+    let protocol = match (matches.get_one::<Protocol>("protocol"), tcp_mode) {
+        (Some(protocol), Some(id)) if *protocol != Protocol::Tcp => {
+            return Err(command.error(
+                ErrorKind::ArgumentConflict,
+                format!("--{id} cannot be used with --protocol {protocol}"),
+            ))
+        }
+        (Some(protocol), _) => *protocol,
+        (None, Some(_)) => Protocol::Tcp,
+        (None, None) => target.protocol.unwrap_or(Protocol::Tcp),
+    };
+    let port_flag_given = matches.value_source("port") == Some(ValueSource::CommandLine);
+    // a port written in the target wins over the default of its scheme or
+    // protocol, which in turn only applies when --port is not given
+    let ports = match (target.port, port_flag_given) {
+        (Some(port), true) if port_flag != [port] => {
+            return Err(command.error(
+                ErrorKind::ArgumentConflict,
+                format!("the target uses port {port} but --port is {port_flag:?}"),
+            ))
+        }
+        (Some(port), _) => vec![port],
+        (None, true) => port_flag,
+        (None, false) if target.unix => Vec::new(),
+        (None, false) => target
+            .scheme_port
+            .or(protocol.default_port())
+            .map_or(port_flag, |port| vec![port]),
+    };
+    if matches.get_flag("traceroute") && ports.len() > 1 {
+        return Err(command.error(
+            ErrorKind::ArgumentConflict,
+            "the traceroute follows the path to a single port",
+        ));
+    }
+    for (id, applies_to) in [
+        ("dns-name", Protocol::Dns),
+        ("dns-type", Protocol::Dns),
+        ("ehlo", Protocol::Smtp),
+        ("ws-path", Protocol::Websocket),
+        ("ws-header", Protocol::Websocket),
+        ("ws-ping", Protocol::Websocket),
+        ("grpc-service", Protocol::Grpc),
+        ("tls", Protocol::Grpc),
+        ("mqtt-version", Protocol::Mqtt),
+        ("mqtt-client-id", Protocol::Mqtt),
+        ("mqtt-username", Protocol::Mqtt),
+        ("mqtt-password-env", Protocol::Mqtt),
+    ] {
+        if matches.value_source(id) == Some(ValueSource::CommandLine) && protocol != applies_to {
+            return Err(command.error(
+                ErrorKind::ArgumentConflict,
+                format!("--{id} only applies to --protocol {applies_to}"),
+            ));
+        }
+    }
+    let source_ports = matches
+        .get_one::<u16>("source-port")
+        .map(|port| *port..=*port)
+        .or_else(|| {
+            matches
+                .get_one::<RangeInclusive<u16>>("source-port-range")
+                .cloned()
+        });
+    // closing first leaves the connection in TIME_WAIT, in which the next
+    // probe from the same source port cannot reopen it
+    let close = match matches.get_one::<Close>("close") {
+        Some(close) => *close,
+        None if matches.contains_id("source-port") => Close::Reset,
+        None => Close::Drop,
+    };
+    let timeout = required::<f32>(&matches, "timeout")?;
+    // without TCP_INFO, a retransmitted SYN is told by the rtt, 1 second
+    // longer at least, which a shorter timeout cuts off
+    if matches.get_flag("exclude-slow-connects")
+        && !matches.get_flag("tcp-info")
+        && timeout != 0.0
+        && timeout <= 1.0
+    {
+        return Err(command.error(
+            ErrorKind::ArgumentConflict,
+            "--exclude-slow-connects needs --tcp-info or a --timeout above 1s",
+        ));
+    }
+    let count = required::<u128>(&matches, "count")?;
+    Ok(UserInput {
+        url: target.host,
+        unix: target.unix,
+        ports,
+        protocol,
         timeout: if timeout == 0.0 {
             None
         } else {
@@ -72,32 +736,844 @@ where
         },
         probes_count: if count == 0 { None } else { Some(count) },
         interval_between_probes: Duration::seconds(1),
-    }
+        interval_jitter: required::<f64>(&matches, "interval-jitter")? / 100.0,
+        interval_align: matches.get_flag("interval-align"),
+        source: matches.get_one::<IpAddr>("source").copied(),
+        interface: matches.get_one::<String>("interface").cloned(),
+        source_ports,
+        hold: matches.get_one::<Duration>("hold").copied(),
+        close,
+        syn: matches.get_flag("syn"),
+        udp: matches.get_flag("udp"),
+        ttl: matches.get_one::<u32>("ttl").copied(),
+        socket_options: SocketOptions {
+            fast_open: matches.get_flag("tfo"),
+            nodelay: matches.get_flag("nodelay"),
+            mss: matches.get_one::<u32>("mss").copied(),
+            tos: matches
+                .get_one::<u32>("tos")
+                .copied()
+                .or_else(|| matches.get_one::<u32>("dscp").map(|dscp| dscp << 2)),
+            congestion: matches.get_one::<String>("congestion").cloned(),
+        },
+        tcp_info: matches.get_flag("tcp-info"),
+        exclude_slow_connects: matches.get_flag("exclude-slow-connects"),
+        traceroute: match matches.get_flag("traceroute") {
+            true => Some(required::<u32>(&matches, "max-hops")?),
+            false => None,
+        },
+        read_banner: matches
+            .get_one::<u16>("read-banner")
+            .map(|len| *len as usize)
+            .or(match matches.contains_id("expect-banner") {
+                true => Some(256),
+                false => None,
+            }),
+        expect_banner: matches.get_one::<Regex>("expect-banner").cloned(),
+        send: matches.get_one::<Vec<u8>>("send").cloned(),
+        expect: matches.get_one::<Regex>("expect").cloned(),
+        dns_name: required::<String>(&matches, "dns-name")?,
+        dns_type: required::<u16>(&matches, "dns-type")?,
+        ehlo: matches.get_one::<String>("ehlo").cloned(),
+        ws_path: match (matches.value_source("ws-path"), target.path) {
+            (Some(ValueSource::CommandLine), _) | (_, None) => {
+                required::<String>(&matches, "ws-path")?
+            }
+            // the path of the target URL, unless --ws-path is given
+            (_, Some(path)) => path,
+        },
+        ws_headers: matches
+            .get_many::<(String, String)>("ws-header")
+            .map_or(Vec::new(), |headers| headers.cloned().collect()),
+        ws_ping: matches.get_flag("ws-ping"),
+        grpc_service: required::<String>(&matches, "grpc-service")?,
+        tls: matches.get_flag("tls"),
+        tls_verify: !matches.get_flag("tls-no-verify"),
+        mqtt_version: required::<MqttVersion>(&matches, "mqtt-version")?,
+        mqtt_client_id: matches.get_one::<String>("mqtt-client-id").cloned(),
+        mqtt_username: matches.get_one::<String>("mqtt-username").cloned(),
+        mqtt_password: matches.get_one::<String>("mqtt-password-env").cloned(),
+        proxy: matches.get_one::<Proxy>("proxy").cloned(),
+        proxy_protocol: matches
+            .get_one::<ProxyProtocol>("proxy-protocol")
+            .map(|version| {
+                let source = matches.get_one::<(IpAddr, Option<u16>)>("proxy-protocol-source");
+                ProxyHeader {
+                    version: *version,
+                    source: source.map(|(ip, _)| *ip),
+                    source_port: source.and_then(|(_, port)| *port),
+                }
+            }),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use chrono::Duration;
+    use clap::error::ErrorKind;
+
+    use crate::{
+        pinger::{Close, MqttVersion, ProxyKind, ProxyProtocol, SocketOptions},
+        target::Protocol,
+        user_input::parse,
+    };
 
-    use crate::user_input::parse;
     #[test]
     fn test_port() {
-        assert_eq!(80, parse(["EXEC_NAME", "example.com", "--port", "80"]).port)
+        assert_eq!(
+            vec![80],
+            parse(["EXEC_NAME", "example.com", "--port", "80"])
+                .unwrap()
+                .ports
+        )
     }
 
     #[test]
     fn test_port_default() {
-        assert_eq!(443, parse(["EXEC_NAME", "example.com"]).port)
+        assert_eq!(
+            vec![443],
+            parse(["EXEC_NAME", "example.com"]).unwrap().ports
+        )
+    }
+
+    #[test]
+    fn test_port_list() {
+        assert_eq!(
+            vec![80, 443, 8000, 8001, 8002],
+            parse(["EXEC_NAME", "example.com", "--port", "80,443,8000-8002"])
+                .unwrap()
+                .ports
+        )
+    }
+
+    #[test]
+    fn test_port_list_duplicates() {
+        assert_eq!(
+            vec![443, 80, 81],
+            parse(["EXEC_NAME", "example.com", "--port", "443,80-81,443,80"])
+                .unwrap()
+                .ports
+        )
+    }
+
+    #[test]
+    fn test_port_list_all() {
+        let ports = parse(["EXEC_NAME", "example.com", "--port", "1-65535,80"])
+            .unwrap()
+            .ports;
+        assert_eq!(ports.len(), 65535);
+        assert_eq!(ports[..2], [1, 2]);
+    }
+
+    #[test]
+    fn test_port_list_invalid() {
+        for ports in ["80,", "8010-8000", "80-", "http", "0", "0-10"] {
+            let e = parse(["EXEC_NAME", "example.com", "--port", ports]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ValueValidation)
+        }
     }
 
     #[test]
     fn test_url() {
-        assert_eq!("example.com", parse(["EXEC_NAME", "example.com"]).url)
+        assert_eq!(
+            "example.com",
+            parse(["EXEC_NAME", "example.com"]).unwrap().url
+        )
     }
 
     #[test]
     fn test_url_as_ip_addr() {
-        assert_eq!("74.6.231.21", parse(["EXEC_NAME", "74.6.231.21"]).url)
+        assert_eq!(
+            "74.6.231.21",
+            parse(["EXEC_NAME", "74.6.231.21"]).unwrap().url
+        )
+    }
+
+    #[test]
+    fn test_url_with_port() {
+        let user_input = parse(["EXEC_NAME", "example.com:8080"]).unwrap();
+        assert_eq!("example.com", user_input.url);
+        assert_eq!(vec![8080], user_input.ports);
+    }
+
+    #[test]
+    fn test_url_ipv6_with_port() {
+        let user_input = parse(["EXEC_NAME", "[2001:db8::1]:22"]).unwrap();
+        assert_eq!("2001:db8::1", user_input.url);
+        assert_eq!(vec![22], user_input.ports);
+    }
+
+    #[test]
+    fn test_url_scheme_default_port() {
+        let user_input = parse(["EXEC_NAME", "ssh://example.com"]).unwrap();
+        assert_eq!("example.com", user_input.url);
+        assert_eq!(vec![22], user_input.ports);
+        assert_eq!(Protocol::Ssh, user_input.protocol);
+    }
+
+    #[test]
+    fn test_url_scheme_port_flag() {
+        assert_eq!(
+            vec![2222],
+            parse(["EXEC_NAME", "ssh://example.com", "--port", "2222"])
+                .unwrap()
+                .ports
+        )
+    }
+
+    #[test]
+    fn test_url_port_same_as_flag() {
+        assert_eq!(
+            vec![80],
+            parse(["EXEC_NAME", "example.com:80", "--port", "80"])
+                .unwrap()
+                .ports
+        )
+    }
+
+    #[test]
+    fn test_url_port_conflict() {
+        let e = parse(["EXEC_NAME", "example.com:80", "--port", "8080"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict)
+    }
+
+    #[test]
+    fn test_protocol() {
+        let protocol = |args: &[&str]| parse(["EXEC_NAME"].iter().chain(args)).unwrap().protocol;
+        assert_eq!(Protocol::Redis, protocol(&["redis://cache"]));
+        assert_eq!(Protocol::Mysql, protocol(&["db", "--protocol", "mysql"]));
+        assert_eq!(
+            Protocol::Tcp,
+            protocol(&["postgres://db", "--protocol", "tcp"])
+        );
+        // plain TCP modes win over the protocol of the scheme
+        assert_eq!(Protocol::Tcp, protocol(&["redis://cache", "--syn"]));
+        let e = parse(["EXEC_NAME", "db", "--protocol", "redis", "--send", "PING"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        let e = parse(["EXEC_NAME", "db", "--protocol", "gopher"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_protocol_default_port() {
+        let ports = |args: &[&str]| parse(["EXEC_NAME"].iter().chain(args)).unwrap().ports;
+        assert_eq!(vec![6379], ports(&["localhost", "--protocol", "redis"]));
+        assert_eq!(
+            vec![7000],
+            ports(&["localhost", "--protocol", "redis", "--port", "7000"])
+        );
+        assert_eq!(vec![443], ports(&["localhost", "--protocol", "tcp"]));
+    }
+
+    #[test]
+    fn test_dns_query() {
+        let user_input = parse(["EXEC_NAME", "dns://9.9.9.9"]).unwrap();
+        assert_eq!(
+            (".", 2),
+            (user_input.dns_name.as_str(), user_input.dns_type)
+        );
+        let user_input = parse([
+            "EXEC_NAME",
+            "dns://9.9.9.9",
+            "--dns-name",
+            "example.com",
+            "--dns-type",
+            "AAAA",
+        ])
+        .unwrap();
+        assert_eq!(vec![53], user_input.ports);
+        assert_eq!(
+            ("example.com", 28),
+            (user_input.dns_name.as_str(), user_input.dns_type)
+        );
+        let e = parse(["EXEC_NAME", "9.9.9.9", "--dns-name", "example.com"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        let e = parse(["EXEC_NAME", "dns://9.9.9.9", "--dns-type", "BOGUS"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_ehlo() {
+        let ehlo = |args: &[&str]| parse(["EXEC_NAME"].iter().chain(args)).unwrap().ehlo;
+        assert_eq!(None, ehlo(&["smtp://mx"]));
+        assert_eq!(Some("localhost".to_owned()), ehlo(&["smtp://mx", "--ehlo"]));
+        assert_eq!(
+            Some("probe.example.com".to_owned()),
+            ehlo(&["mx", "--protocol", "smtp", "--ehlo", "probe.example.com"])
+        );
+        let e = parse(["EXEC_NAME", "ftp://files", "--ehlo"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_proxy() {
+        let proxy = parse([
+            "EXEC_NAME",
+            "db.internal",
+            "--proxy",
+            "socks5://bastion:1080",
+        ])
+        .unwrap()
+        .proxy
+        .unwrap();
+        assert_eq!(
+            (ProxyKind::Socks5, "bastion", 1080),
+            (proxy.kind, proxy.host.as_str(), proxy.port)
+        );
+        let e = parse(["EXEC_NAME", "db.internal", "--proxy", "ftp://bastion"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+        let e = parse([
+            "EXEC_NAME",
+            "db.internal",
+            "--proxy",
+            "http://bastion:3128",
+            "--syn",
+        ])
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_proxy_protocol() {
+        let header = parse([
+            "EXEC_NAME",
+            "backend",
+            "--proxy-protocol",
+            "v2",
+            "--proxy-protocol-source",
+            "[2001:db8::7]:4000",
+        ])
+        .unwrap()
+        .proxy_protocol
+        .unwrap();
+        assert_eq!(header.version, ProxyProtocol::V2);
+        assert_eq!(header.source, Some("2001:db8::7".parse().unwrap()));
+        assert_eq!(header.source_port, Some(4000));
+        let header = parse(["EXEC_NAME", "backend", "--proxy-protocol", "v1"])
+            .unwrap()
+            .proxy_protocol
+            .unwrap();
+        assert_eq!((header.source, header.source_port), (None, None));
+        let e = parse(["EXEC_NAME", "backend", "--proxy-protocol", "v3"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+        let e = parse([
+            "EXEC_NAME",
+            "backend",
+            "--proxy-protocol-source",
+            "192.0.2.1",
+        ])
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument);
+        let e = parse([
+            "EXEC_NAME",
+            "backend",
+            "--proxy-protocol",
+            "v1",
+            "--proxy",
+            "socks5://127.0.0.1:1080",
+        ])
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        let user_input = parse(["EXEC_NAME", "unix:/run/app.sock"]).unwrap();
+        assert_eq!("/run/app.sock", user_input.url);
+        assert!(user_input.unix);
+        assert!(user_input.ports.is_empty());
+        for flag in [["--port", "80"], ["--send", "PING"], ["--protocol", "tcp"]] {
+            let e = parse(["EXEC_NAME", "unix:/run/app.sock", flag[0], flag[1]]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        let e = parse(["EXEC_NAME", "gopher://example.com"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation)
+    }
+
+    #[test]
+    fn test_source() {
+        let user_input = parse(["EXEC_NAME", "1.2.3.4", "--source", "10.0.0.2"]).unwrap();
+        assert_eq!(Some("10.0.0.2".parse().unwrap()), user_input.source);
+        assert_eq!(None, parse(["EXEC_NAME", "1.2.3.4"]).unwrap().source);
+    }
+
+    #[test]
+    fn test_invalid_source() {
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--source", "eth0"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation)
+    }
+
+    #[test]
+    fn test_interface() {
+        assert_eq!(
+            Some("eth1".to_owned()),
+            parse(["EXEC_NAME", "1.2.3.4", "--interface", "eth1"])
+                .unwrap()
+                .interface
+        )
+    }
+
+    #[test]
+    fn test_source_port() {
+        assert_eq!(
+            Some(40000..=40000),
+            parse(["EXEC_NAME", "1.2.3.4", "--source-port", "40000"])
+                .unwrap()
+                .source_ports
+        )
+    }
+
+    #[test]
+    fn test_source_port_close() {
+        let close = |args: &[&str]| parse(["EXEC_NAME"].iter().chain(args)).unwrap().close;
+        assert_eq!(Close::Reset, close(&["1.2.3.4", "--source-port", "40000"]));
+        assert_eq!(
+            Close::Graceful,
+            close(&["1.2.3.4", "--source-port", "40000", "--close", "fin"])
+        );
+        assert_eq!(
+            Close::Drop,
+            close(&["1.2.3.4", "--source-port-range", "40000-40010"])
+        );
+    }
+
+    #[test]
+    fn test_source_port_range() {
+        assert_eq!(
+            Some(40000..=40010),
+            parse(["EXEC_NAME", "1.2.3.4", "--source-port-range", "40000-40010"])
+                .unwrap()
+                .source_ports
+        )
+    }
+
+    #[test]
+    fn test_source_port_range_invalid() {
+        for range in ["40000", "40010-40000", "40000-70000", "0-10"] {
+            let e = parse(["EXEC_NAME", "1.2.3.4", "--source-port-range", range]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ValueValidation)
+        }
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--source-port", "0"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation)
+    }
+
+    #[test]
+    fn test_source_port_conflict() {
+        let e = parse([
+            "EXEC_NAME",
+            "1.2.3.4",
+            "--source-port",
+            "40000",
+            "--source-port-range",
+            "40000-40010",
+        ])
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict)
+    }
+
+    #[test]
+    fn test_hold() {
+        for (hold, expected) in [
+            ("5", Duration::seconds(5)),
+            ("2s", Duration::seconds(2)),
+            ("1.5s", Duration::milliseconds(1500)),
+            ("250ms", Duration::milliseconds(250)),
+            ("1m", Duration::minutes(1)),
+        ] {
+            assert_eq!(
+                Some(expected),
+                parse(["EXEC_NAME", "1.2.3.4", "--hold", hold])
+                    .unwrap()
+                    .hold
+            )
+        }
+    }
+
+    #[test]
+    fn test_hold_invalid() {
+        for hold in ["-1s", "1h", "forever"] {
+            let e = parse(["EXEC_NAME", "1.2.3.4", "--hold", hold]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ValueValidation)
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        assert_eq!(Close::Drop, parse(["EXEC_NAME", "1.2.3.4"]).unwrap().close);
+        assert_eq!(
+            Close::Graceful,
+            parse(["EXEC_NAME", "1.2.3.4", "--close", "fin"])
+                .unwrap()
+                .close
+        );
+        assert_eq!(
+            Close::Reset,
+            parse(["EXEC_NAME", "1.2.3.4", "--close", "rst"])
+                .unwrap()
+                .close
+        );
+    }
+
+    #[test]
+    fn test_syn() {
+        assert!(!parse(["EXEC_NAME", "1.2.3.4"]).unwrap().syn);
+        assert!(parse(["EXEC_NAME", "1.2.3.4", "--syn"]).unwrap().syn);
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--syn", "--hold", "1s"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict)
+    }
+
+    #[test]
+    fn test_websocket() {
+        let user_input = parse([
+            "EXEC_NAME",
+            "ws://gateway",
+            "--ws-path",
+            "/live?v=2",
+            "--ws-header",
+            "Origin: https://example.com",
+            "--ws-header",
+            "Authorization:Bearer abc",
+            "--ws-ping",
+        ])
+        .unwrap();
+        assert_eq!(user_input.protocol, Protocol::Websocket);
+        assert_eq!(user_input.ports, [80]);
+        assert_eq!(user_input.ws_path, "/live?v=2");
+        assert_eq!(
+            user_input.ws_headers,
+            [
+                ("Origin".to_owned(), "https://example.com".to_owned()),
+                ("Authorization".to_owned(), "Bearer abc".to_owned())
+            ]
+        );
+        assert!(user_input.ws_ping);
+        let user_input = parse(["EXEC_NAME", "ws://gateway/live"]).unwrap();
+        assert_eq!(user_input.ws_path, "/live");
+        let e = parse(["EXEC_NAME", "gateway", "--ws-ping"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        let e = parse(["EXEC_NAME", "ws://gateway", "--ws-header", "Origin"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_grpc() {
+        let user_input = parse([
+            "EXEC_NAME",
+            "grpc://orders:50051",
+            "--grpc-service",
+            "orders.v1.Orders",
+            "--tls",
+            "--tls-no-verify",
+        ])
+        .unwrap();
+        assert_eq!(user_input.protocol, Protocol::Grpc);
+        assert_eq!(user_input.ports, [50051]);
+        assert_eq!(user_input.grpc_service, "orders.v1.Orders");
+        assert!(user_input.tls);
+        assert!(!user_input.tls_verify);
+        let user_input = parse(["EXEC_NAME", "orders", "--protocol", "grpc"]).unwrap();
+        assert_eq!(user_input.grpc_service, "");
+        assert!(!user_input.tls);
+        let e = parse(["EXEC_NAME", "orders", "--tls"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_mqtt() {
+        std::env::set_var("RUSTCPING_TEST_MQTT_PASSWORD", "secret");
+        let user_input = parse([
+            "EXEC_NAME",
+            "mqtt://broker",
+            "--mqtt-version",
+            "5",
+            "--mqtt-client-id",
+            "probe-1",
+            "--mqtt-username",
+            "sensor",
+            "--mqtt-password-env",
+            "RUSTCPING_TEST_MQTT_PASSWORD",
+        ])
+        .unwrap();
+        assert_eq!(user_input.protocol, Protocol::Mqtt);
+        assert_eq!(user_input.ports, [1883]);
+        assert_eq!(user_input.mqtt_version, MqttVersion::V5);
+        assert_eq!(user_input.mqtt_client_id.as_deref(), Some("probe-1"));
+        assert_eq!(user_input.mqtt_username.as_deref(), Some("sensor"));
+        assert_eq!(user_input.mqtt_password.as_deref(), Some("secret"));
+        let e = parse([
+            "EXEC_NAME",
+            "mqtt://broker",
+            "--mqtt-username",
+            "sensor",
+            "--mqtt-password-env",
+            "RUSTCPING_TEST_MQTT_UNSET",
+        ])
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+        let e = parse(["EXEC_NAME", "broker", "--mqtt-username", "sensor"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_udp() {
+        assert!(!parse(["EXEC_NAME", "1.2.3.4"]).unwrap().udp);
+        let user_input = parse(["EXEC_NAME", "dns://1.2.3.4", "--udp"]).unwrap();
+        assert!(user_input.udp);
+        assert_eq!(user_input.ports, [53]);
+        assert_eq!(user_input.protocol, Protocol::Tcp);
+        for flag in [["--hold", "1s"], ["--proxy", "socks5://proxy"]] {
+            let e = parse(["EXEC_NAME", "1.2.3.4", "--udp", flag[0], flag[1]]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        }
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--udp", "--protocol", "redis"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let user_input = parse(["EXEC_NAME", "1.2.3.4"]).unwrap();
+        assert_eq!(user_input.socket_options, SocketOptions::default());
+        assert_eq!(user_input.ttl, None);
+        let user_input = parse([
+            "EXEC_NAME",
+            "1.2.3.4",
+            "--tfo",
+            "--send",
+            "PING",
+            "--nodelay",
+            "--mss",
+            "1200",
+            "--tos",
+            "0x10",
+            "--ttl",
+            "5",
+            "--congestion",
+            "bbr",
+        ])
+        .unwrap();
+        assert_eq!(
+            user_input.socket_options,
+            SocketOptions {
+                fast_open: true,
+                nodelay: true,
+                mss: Some(1200),
+                tos: Some(0x10),
+                congestion: Some("bbr".to_owned()),
+            }
+        );
+        assert_eq!(user_input.ttl, Some(5));
+        assert!(!user_input.tcp_info);
+        assert!(
+            parse(["EXEC_NAME", "1.2.3.4", "--tcp-info"])
+                .unwrap()
+                .tcp_info
+        );
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--tfo"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument);
+        let e = parse(["EXEC_NAME", "redis://1.2.3.4", "--tfo"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument);
+        for args in [
+            ["--read-banner", "--send"],
+            ["--proxy-protocol=v1", "--send"],
+        ] {
+            let e = parse(["EXEC_NAME", "1.2.3.4", "--tfo", args[0], args[1], "x"]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        }
+        let user_input = parse(["EXEC_NAME", "1.2.3.4", "--dscp", "46"]).unwrap();
+        assert_eq!(user_input.socket_options.tos, Some(0xb8));
+        for args in [
+            ["--tos", "256"],
+            ["--tos", "0xg0"],
+            ["--dscp", "64"],
+            ["--ttl", "0"],
+        ] {
+            let e = parse(["EXEC_NAME", "1.2.3.4", args[0], args[1]]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ValueValidation);
+        }
+        for args in [
+            ["--tos", "16", "--dscp", "4"],
+            ["--udp", "--mss", "1200", "--nodelay"],
+            ["--syn", "--ttl", "5", "--nodelay"],
+            ["--traceroute", "--ttl", "5", "--nodelay"],
+        ] {
+            let e =
+                parse(["EXEC_NAME", "1.2.3.4", args[0], args[1], args[2], args[3]]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        }
+        // the trace only opens connections, so the exchanges are not made
+        for args in [
+            &["--send", "x"][..],
+            &["--send", "x", "--expect", "y"],
+            &["--read-banner"],
+            &["--expect-banner", "^SSH"],
+            &["--tcp-info"],
+            &["--exclude-slow-connects"],
+            &["--send", "x", "--tfo"],
+        ] {
+            let e = parse(["EXEC_NAME", "1.2.3.4", "--traceroute"].iter().chain(args)).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ArgumentConflict, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn test_exclude_slow_connects() {
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--exclude-slow-connects"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        for args in [
+            &["--timeout", "3"][..],
+            &["--timeout", "0"],
+            &["--tcp-info"],
+        ] {
+            let user_input = parse(
+                ["EXEC_NAME", "1.2.3.4", "--exclude-slow-connects"]
+                    .iter()
+                    .chain(args),
+            )
+            .unwrap();
+            assert!(user_input.exclude_slow_connects);
+        }
+    }
+
+    #[test]
+    fn test_interval_jitter_and_align() {
+        let user_input = parse(["EXEC_NAME", "1.2.3.4"]).unwrap();
+        assert_eq!(user_input.interval_jitter, 0.0);
+        assert!(!user_input.interval_align);
+        let user_input = parse([
+            "EXEC_NAME",
+            "1.2.3.4",
+            "--interval-jitter",
+            "20",
+            "--interval-align",
+        ])
+        .unwrap();
+        assert_eq!(user_input.interval_jitter, 0.2);
+        assert!(user_input.interval_align);
+        for jitter in ["-1", "101", "a"] {
+            let e = parse(["EXEC_NAME", "1.2.3.4", "--interval-jitter", jitter]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ValueValidation);
+        }
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--traceroute", "--interval-align"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_traceroute() {
+        assert_eq!(None, parse(["EXEC_NAME", "1.2.3.4"]).unwrap().traceroute);
+        assert_eq!(
+            Some(30),
+            parse(["EXEC_NAME", "1.2.3.4", "--traceroute"])
+                .unwrap()
+                .traceroute
+        );
+        assert_eq!(
+            Some(12),
+            parse(["EXEC_NAME", "1.2.3.4", "--traceroute", "--max-hops", "12"])
+                .unwrap()
+                .traceroute
+        );
+    }
+
+    #[test]
+    fn test_traceroute_single_port() {
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--traceroute", "--port", "80,443"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--max-hops", "12"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn test_read_banner() {
+        assert_eq!(None, parse(["EXEC_NAME", "1.2.3.4"]).unwrap().read_banner);
+        assert_eq!(
+            Some(256),
+            parse(["EXEC_NAME", "1.2.3.4", "--read-banner"])
+                .unwrap()
+                .read_banner
+        );
+        assert_eq!(
+            Some(64),
+            parse(["EXEC_NAME", "1.2.3.4", "--read-banner=64"])
+                .unwrap()
+                .read_banner
+        );
+        // the host after the flag is not taken for its length
+        let user_input = parse(["EXEC_NAME", "--read-banner", "example.com"]).unwrap();
+        assert_eq!(Some(256), user_input.read_banner);
+        assert_eq!("example.com", user_input.url);
+    }
+
+    #[test]
+    fn test_expect_banner() {
+        let user_input = parse(["EXEC_NAME", "1.2.3.4", "--expect-banner", "^SSH-2\\.0-"]).unwrap();
+        assert_eq!(Some(256), user_input.read_banner);
+        assert!(user_input
+            .expect_banner
+            .unwrap()
+            .is_match(b"SSH-2.0-OpenSSH_9.6"));
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--expect-banner", "(SSH"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_send_string() {
+        assert_eq!(
+            Some(b"PING\r\n\x00\xff".to_vec()),
+            parse(["EXEC_NAME", "1.2.3.4", "--send", "PING\\r\\n\\0\\xff"])
+                .unwrap()
+                .send
+        );
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--send", "\\q"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_send_hex() {
+        assert_eq!(
+            Some(vec![0xde, 0xad, 0xbe, 0xef]),
+            parse(["EXEC_NAME", "1.2.3.4", "--send", "hex:de ad BE EF"])
+                .unwrap()
+                .send
+        );
+        for hex in ["hex:abc", "hex:zz"] {
+            let e = parse(["EXEC_NAME", "1.2.3.4", "--send", hex]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ValueValidation);
+        }
+    }
+
+    #[test]
+    fn test_send_file() {
+        let path = std::env::temp_dir().join("rustcping_test_send_file");
+        std::fs::write(&path, b"\x01\x02").unwrap();
+        let arg = format!("@{}", path.display());
+        assert_eq!(
+            Some(vec![1, 2]),
+            parse(["EXEC_NAME", "1.2.3.4", "--send", &arg])
+                .unwrap()
+                .send
+        );
+        std::fs::remove_file(path).unwrap();
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--send", "@/nonexistent"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_expect() {
+        let expect = |value| {
+            parse(["EXEC_NAME", "1.2.3.4", "--send", "PING", "--expect", value])
+                .unwrap()
+                .expect
+                .unwrap()
+        };
+        assert!(expect("^\\+PONG").is_match(b"+PONG\r\n"));
+        assert!(expect("hex:ff00").is_match(b"\xff\x00\x01"));
+        assert!(!expect("hex:ff00").is_match(b"\x01\xff\x00"));
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--expect", "PONG"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument);
     }
 
     #[test]
@@ -105,6 +1581,7 @@ mod tests {
         assert_eq!(
             Duration::seconds(3),
             parse(["EXEC_NAME", "1.2.3.4", "--timeout", "3"])
+                .unwrap()
                 .timeout
                 .unwrap()
         )
@@ -115,6 +1592,7 @@ mod tests {
         assert_eq!(
             Duration::milliseconds(3230),
             parse(["EXEC_NAME", "1.2.3.4", "--timeout", "3.23"])
+                .unwrap()
                 .timeout
                 .unwrap()
         )
@@ -124,34 +1602,58 @@ mod tests {
     fn test_zero_timeout() {
         assert_eq!(
             None,
-            parse(["EXEC_NAME", "1.2.3.4", "--timeout", "0"]).timeout
+            parse(["EXEC_NAME", "1.2.3.4", "--timeout", "0"])
+                .unwrap()
+                .timeout
         )
     }
 
     #[test]
-    #[should_panic]
     fn test_negative_timeout() {
-        _ = parse(["EXEC_NAME", "1.2.3.4", "--timeout", "-2.3"])
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--timeout", "-2.3"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation)
+    }
+
+    #[test]
+    fn test_invalid_timeout() {
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--timeout", "soon"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation)
+    }
+
+    #[test]
+    fn test_invalid_port() {
+        let e = parse(["EXEC_NAME", "1.2.3.4", "--port", "65536"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation)
+    }
+
+    #[test]
+    fn test_missing_url() {
+        let e = parse(["EXEC_NAME", "--port", "80"]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument)
     }
 
     #[test]
     fn test_probes_count_positive() {
         assert_eq!(
             Some(3),
-            parse(["EXEC_NAME", "1.2.3.4", "--count", "3"]).probes_count
+            parse(["EXEC_NAME", "1.2.3.4", "--count", "3"])
+                .unwrap()
+                .probes_count
         )
     }
 
     #[test]
     fn test_probes_count_none() {
-        assert_eq!(None, parse(["EXEC_NAME", "1.2.3.4"]).probes_count)
+        assert_eq!(None, parse(["EXEC_NAME", "1.2.3.4"]).unwrap().probes_count)
     }
 
     #[test]
     fn test_probes_count_none_zero() {
         assert_eq!(
             None,
-            parse(["EXEC_NAME", "1.2.3.4", "--count", "0"]).probes_count
+            parse(["EXEC_NAME", "1.2.3.4", "--count", "0"])
+                .unwrap()
+                .probes_count
         )
     }
 }